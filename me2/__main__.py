@@ -14,6 +14,7 @@ from time import sleep, strftime
 
 from .bits import bit_indices, ffs
 from .dt import *
+from .query import *
 
 # Parse command-line arguments.
 if len(sys.argv) < 2:
@@ -35,7 +35,8 @@ class Ally(enum.Flag):
   def conj(self, conjunction: str = "and") -> str:
     """Converts this Ally into a human-readable string with the specified
     conjunction, if applicable."""
-    if self.name:
+    # Since Python 3.11, compound members also have a name (e.g., "Grunt|Tali").
+    if len(self) == 1 and self.name:
       return self.name
     if self.value == 0:
       return "nobody"
@@ -5,11 +5,11 @@
 #
 
 import statistics
-from collections.abc import Callable
+from collections.abc import Callable, Iterable
 from functools import partial, reduce
 from itertools import chain
 from operator import or_ as op_or, and_ as op_and
-from typing import TypeVar
+from typing import Any, NamedTuple, Optional, TypeVar
 
 from .ally import Ally
 from . import bits
@@ -63,8 +63,34 @@ class UnexpectedlyVictimlessError(Exception):
   """Custom error type for a call to get_victim() resulting in zero victims."""
   pass
 
-def get_victim(team: int, priority: list[int]) -> int:
-  """Selects the teammate who should die based on the given priority."""
+class InvalidTeamError(ValueError):
+  """Custom error type for a team that cannot exist in the game."""
+  pass
+
+_SAMARA_AND_MORINTH = (Ally.Samara | Ally.Morinth).value
+
+def validate_team(team: int):
+  """Raises an InvalidTeamError if team contains both Samara and Morinth.
+
+  Recruiting Morinth always kills Samara, and several priority lists contain
+  both of them, so such a team would produce plausible but impossible victims.
+  """
+  if team & _SAMARA_AND_MORINTH == _SAMARA_AND_MORINTH:
+    raise InvalidTeamError(f"Samara and Morinth together: {Ally(team)}")
+
+def get_victim(team: int,
+               priority: list[int],
+               exclude: int = 0,
+               *,
+               validate: bool = True) -> int:
+  """Selects the teammate who should die based on the given priority.
+
+  Teammates in exclude (e.g., earlier victims) are never selected. Set validate
+  to False to skip validate_team() for a team that is valid by construction.
+  """
+  team &= ~exclude
+  if validate:
+    validate_team(team)
   if (victim := next(filter(partial(op_and, team), priority), 0)):
     return victim
   # It should be impossible to encounter a situation where none of the teammates
@@ -72,6 +98,21 @@ def get_victim(team: int, priority: list[int]) -> int:
   raise UnexpectedlyVictimlessError(
     f"No victim ({hex(team)} & {hex(reduce(op_or, priority, 0))} == 0)")
 
+def get_victims(team: int, priorities: Iterable[list[int]]) -> list[int]:
+  """Selects one victim per priority in sequence.
+
+  Each victim is removed from the team before the next priority is applied, so
+  no teammate is selected twice. If none of the remaining teammates are in a
+  priority list, it is skipped, and its victim is zero.
+  """
+  validate_team(team)
+  victims: list[int] = []
+  for priority in priorities:
+    victim = next(filter(partial(op_and, team), priority), 0)
+    victims.append(victim)
+    team &= ~victim
+  return victims
+
 
 # Loyal allies who are left behind to defend during the final battle are
 # assigned defense scores according to their "innate defensiveness". If an ally
@@ -115,23 +156,193 @@ _DEFENSE_TOLL_FORMULAE: list[Callable[[float], int]] = [
         else 1 if x < 2
         else 0)
 ]
-_DTF_LAST_INDEX = len(_DEFENSE_TOLL_FORMULAE) - 1
 
-def _get_defense_toll(team: int, loyal: int) -> int:
+
+class RuleSet(NamedTuple):
+  """Parameterizes the death priorities and defense formulae of the final
+  mission.
+
+  Every field defaults to the tables defined in this module. Alternative rules
+  can be derived with _replace().
+  """
+  no_armor_upgrade: list[int] = DP_NO_ARMOR_UPGRADE
+  no_shield_upgrade: list[int] = DP_NO_SHIELD_UPGRADE
+  no_weapon_upgrade: list[int] = DP_NO_WEAPON_UPGRADE
+  the_long_walk: list[int] = DP_THE_LONG_WALK
+  defense: list[int] = _DP_DEFENSE
+  defense_score: dict[int, int] = _DEFENSE_SCORE
+  defense_toll_formulae: list[Callable[[float], int]] = _DEFENSE_TOLL_FORMULAE
+
+# The rules used to generate the included data file.
+DEFAULT_RULES = RuleSet()
+
+# Increment this whenever the structure returned by export_tables() changes.
+TABLES_VERSION = 1
+
+# The priority lists in a rule set, in the order they are applied.
+PRIORITY_NAMES = ("no_armor_upgrade", "no_shield_upgrade", "no_weapon_upgrade",
+                  "the_long_walk", "defense")
+
+# Human-readable descriptions of the single-victim priority lists.
+_PRIORITY_DESCRIPTIONS = {
+  "no_armor_upgrade": "armor-failure",
+  "no_shield_upgrade": "shield-failure",
+  "no_weapon_upgrade": "weapon-failure",
+  "the_long_walk": "Long Walk"
+}
+
+class VictimExplanation(NamedTuple):
+  """Explains the selection of a victim from a priority list.
+
+  If no teammate is in the priority list, victim is zero and rank is -1.
+  Teammates who are in the list but ranked below the victim are in ahead_of, in
+  priority order. Teammates who are not in the list at all are in ineligible.
+  """
+  name: str
+  victim: int
+  rank: int
+  ahead_of: tuple[int, ...]
+  ineligible: int
+
+  def __str__(self) -> str:
+    description = _PRIORITY_DESCRIPTIONS[self.name]
+    if not self.victim:
+      return f"Nobody on your team is in the {description} priority."
+    if not self.ahead_of:
+      return (f"{Ally(self.victim)} is the only member of your team in the "
+              f"{description} priority.")
+    names = [Ally(ally).conj() for ally in self.ahead_of]
+    if len(names) > 2:
+      names[-1] = f"and {names[-1]}"
+    ahead_of = (", " if len(names) > 2 else " and ").join(names)
+    return (f"{Ally(self.victim)} is highest in the {description} priority "
+            f"among your team (ahead of {ahead_of}).")
+
+def explain_victim(team: int,
+                   name: str,
+                   rules: RuleSet = DEFAULT_RULES) -> VictimExplanation:
+  """Explains which teammate would be selected from the named priority list of
+  the given rules and why.
+
+  The name must be one of PRIORITY_NAMES other than "defense", which selects
+  its victims differently (see get_defense_victims()).
+  """
+  if name not in _PRIORITY_DESCRIPTIONS:
+    raise ValueError(f"Not a single-victim priority: {name}")
+  validate_team(team)
+  priority: list[int] = getattr(rules, name)
+  ranked = [ally for ally in priority if ally & team]
+  ineligible = team & ~reduce(op_or, priority, 0)
+  if not ranked:
+    return VictimExplanation(name, 0, -1, (), ineligible)
+  victim = ranked[0]
+  return VictimExplanation(name, victim, priority.index(victim),
+                           tuple(ranked[1:]), ineligible)
+
+def diagnose(victim: int,
+             team: int,
+             rules: RuleSet = DEFAULT_RULES) -> list[str]:
+  """Lists the names of the single-victim priority lists, in the order they
+  apply, for which victim is the highest-priority member of team.
+
+  Squad selections in the cargo bay and during The Long Walk can shift the
+  victim further down a list, so this only reports the default victims.
+  """
+  validate_team(team)
+  defaults = {name: next(filter(partial(op_and, team), getattr(rules, name)), 0)
+              for name in _PRIORITY_DESCRIPTIONS}
+  return [name for name, default in defaults.items() if default == victim]
+
+def export_tables(rules: RuleSet = DEFAULT_RULES) -> dict[str, Any]:
+  """Exports the priority lists and defense scores of the given rules as a
+  JSON-serializable dictionary keyed by ally names.
+
+  The defense toll formulae are code, not data, so they are not exported.
+  """
+  return {
+    "version": TABLES_VERSION,
+    "priorities": [
+      {"name": name, "allies": [Ally(x).name for x in getattr(rules, name)]}
+      for name in PRIORITY_NAMES
+    ],
+    "defense_scores": {
+      ally.name: rules.defense_score[ally.value] for ally in Ally
+      if ally.value in rules.defense_score
+    }
+  }
+
+def _get_defense_toll(team: int, loyal: int, rules: RuleSet) -> int:
   """Computes the death toll for the defense team."""
   if not (team_size := bits.popcount(team)):
     raise ValueError("Zero defending allies")
   # Compute the average defense score. Disloyal allies' scores are reduced.
-  score_for = lambda ally: _DEFENSE_SCORE[ally] - bool(ally & ~loyal)
+  score_for = lambda ally: rules.defense_score[ally] - bool(ally & ~loyal)
   score = statistics.fmean(score_for(ally) for ally in bits.bits(team))
-  formula_index = min(team_size, _DTF_LAST_INDEX)
-  return _DEFENSE_TOLL_FORMULAE[formula_index](score)
+  formulae = rules.defense_toll_formulae
+  return formulae[min(team_size, len(formulae) - 1)](score)
+
+def score(ally: int,
+          loyal: bool,
+          rules: RuleSet = DEFAULT_RULES) -> Optional[int]:
+  """Returns the defense score of a single ally or None if ally is not exactly
+  one ally."""
+  if bits.popcount(ally) != 1 or ally not in rules.defense_score:
+    return None
+  return rules.defense_score[ally] - (not loyal)
+
+def tiers(rules: RuleSet = DEFAULT_RULES) -> list[tuple[int, int]]:
+  """Groups allies by their base defense score in descending order of score."""
+  groups: dict[int, int] = {}
+  for ally, base in rules.defense_score.items():
+    groups[base] = groups.get(base, 0) | ally
+  return sorted(groups.items(), reverse=True)
+
+class TollDistribution(NamedTuple):
+  """Distribution of defense death tolls over a set of loyalty assignments.
+
+  counts maps each toll to the number of assignments resulting in it. mean is
+  the expected toll assuming every assignment is equally likely.
+  """
+  counts: dict[int, int]
+  min: int
+  max: int
+  mean: float
+
+def sweep_defense(team: int,
+                  rules: RuleSet = DEFAULT_RULES) -> TollDistribution:
+  """Computes the defense death toll for every loyal subset of team."""
+  validate_team(team)
+  counts: dict[int, int] = {}
+  loyal = 0
+  while True:
+    toll = _get_defense_toll(team, loyal, rules)
+    counts[toll] = counts.get(toll, 0) + 1
+    # Advance to the next subset of team.
+    if (loyal := (loyal - team) & team) == 0:
+      break
+  total = sum(counts.values())
+  mean = sum(toll * n for toll, n in counts.items()) / total
+  return TollDistribution(dict(sorted(counts.items())), min(counts),
+                          max(counts), mean)
+
+def get_defense_victims(team: int,
+                        loyal: int,
+                        rules: RuleSet = DEFAULT_RULES,
+                        exclude: int = 0,
+                        *,
+                        validate: bool = True) -> int:
+  """Selects the defending teammates who should die.
 
-def get_defense_victims(team: int, loyal: int) -> int:
-  """Selects the defending teammates who should die."""
-  toll = _get_defense_toll(team, loyal)
+  Teammates in exclude (e.g., earlier victims) neither defend nor die. Set
+  validate to False to skip validate_team() for a team that is valid by
+  construction.
+  """
+  team &= ~exclude
+  if validate:
+    validate_team(team)
+  toll = _get_defense_toll(team, loyal, rules)
   # Disloyal teammates are chosen as victims before loyal ones.
-  disloyal_filter = filter(partial(op_and, team & ~loyal), _DP_DEFENSE)
-  loyal_filter = filter(partial(op_and, team & loyal), _DP_DEFENSE)
+  disloyal_filter = filter(partial(op_and, team & ~loyal), rules.defense)
+  loyal_filter = filter(partial(op_and, team & loyal), rules.defense)
   priority = chain(disloyal_filter, loyal_filter)
   return reduce(op_or, (ally for _, ally in zip(range(toll), priority)), 0)
\ No newline at end of file
@@ -5,12 +5,14 @@
 #
 
 from __future__ import annotations
+from collections.abc import Callable
 import enum
 from functools import reduce
 from itertools import combinations
 from operator import or_ as op_or
 import pickle
-from typing import Any, Optional
+from threading import Timer
+from typing import Any, NamedTuple, Optional
 
 from .ally import *
 from . import bits, death, encdec, util
@@ -32,37 +34,126 @@ def describe_outcome(encoded: int, *, brief: bool = False) -> str:
   output += f"Loyal:    {loyalty}\n"
   return output + f"Crew:     {'survived' if crew else 'dead'}"
 
+def diff_outcomes(a: int, b: int) -> list[str]:
+  """Lists the differences between two encoded outcomes as human-readable
+  lines like "Newly dead: Mordin".
 
-def describe_traversal(traversal: int) -> str:
-  """Produces a human-readable string describing the encoded traversal."""
-  # First, decode the traversal using the same variable sequence as the
-  # encoding.
+  Loyalty changes are only listed for allies who survived in both outcomes.
+  Identical outcomes produce an empty list.
+  """
+  before = encdec.decode_outcome(a)
+  after = encdec.decode_outcome(b)
+  survived = {False: "dead", True: "survived"}
+  both = before.spared & after.spared
+  changes = {
+    "Newly spared": after.spared & ~before.spared,
+    "Newly dead": before.spared & ~after.spared,
+    "Newly loyal": after.loyalty & ~before.loyalty & both,
+    "No longer loyal": before.loyalty & ~after.loyalty & both
+  }
+  diff = [f"{label}: {allies}" for label, allies in changes.items() if allies]
+  if before.crew != after.crew:
+    diff.append(f"Crew: {survived[before.crew]} -> {survived[after.crew]}")
+  if before.shepard != after.shepard:
+    diff.append(
+      f"Shepard: {survived[before.shepard]} -> {survived[after.shepard]}")
+  return diff
+
+
+class SquadAdvice(NamedTuple):
+  """Which allies to pick for a squad and which to leave behind."""
+  take: Ally
+  leave: Ally
+
+  def __str__(self) -> str:
+    output = ""
+    if self.take:
+      output += f"pick {self.take}"
+      if self.leave:
+        output += " and "
+    if self.leave:
+      output += f"make sure to leave {self.leave} behind"
+    return output
+
+
+class DecodedTraversal(NamedTuple):
+  """The decisions of an encoded traversal.
+
+  Decisions that do not apply to the traversal are None.
+  """
+  recruits: Ally
+  loyalty: Ally
+  armor: bool
+  shield: bool
+  weapon: bool
+  cargo_bay_squad: Optional[SquadAdvice]
+  tech: Ally
+  first_leader: Optional[bool]
+  ideal_leaders: Ally
+  biotic: Ally
+  second_leader: Ally
+  escort: Ally
+  walk_squad: Optional[SquadAdvice]
+  final_squad: Ally
+
+  @property
+  def team(self) -> Ally:
+    """The allies who take part in the final mission."""
+    team = self.recruits | REQUIRED
+    # Recruiting Morinth always kills Samara.
+    if team & Ally.Morinth:
+      team &= ~Ally.Samara
+    return team
+
+  def first_leader_advice(self) -> Optional[str]:
+    """Describes whom to pick to lead the second fireteam, if it matters."""
+    if self.first_leader is None:
+      return None
+    anyone_except = "" if self.first_leader else "anyone except "
+    return f"{anyone_except}{self.ideal_leaders.conj('or')}"
+
+def decode_traversal(traversal: int) -> DecodedTraversal:
+  """Decodes the traversal using the same variable sequence as the encoding."""
   decoder = encdec.Decoder(traversal)
   recruits = decoder.decode_ally_optional()
   loyal = decoder.decode_ally_loyalty()
   upgraded_armor = decoder.decode_bool()
   upgraded_shield = decoder.decode_bool()
   upgraded_weapon = decoder.decode_bool()
-  cbs_invert = False
-  cbs_picks: list[Ally] = []
+  cargo_bay_squad = None
   if not upgraded_shield:
     cbs_invert, cbs_picks = decoder.decode_choices()
+    cargo_bay_squad = SquadAdvice(
+      reduce(op_or, cbs_picks[:-1] if cbs_invert else cbs_picks, NOBODY),
+      cbs_picks[-1] if cbs_invert else NOBODY)
   tech = decoder.decode_ally_index()
-  leader1 = False
+  leader1 = None
   ideal_leaders = NOBODY
-  has_leader1 = bool(tech & loyal & IDEAL_TECHS)
-  if has_leader1:
+  if tech & loyal & IDEAL_TECHS:
     leader1 = decoder.decode_bool()
     ideal_leaders = decoder.decode_ideal_leaders()
   biotic = decoder.decode_ally_index()
   leader2 = decoder.decode_ally_index()
   escort = decoder.decode_ally_index()
-  tlw_invert = False
-  tlw_unpicks: list[Ally] = []
-  has_tlw_unpicks = decoder.decode_bool()
-  if has_tlw_unpicks:
+  walk_squad = None
+  if decoder.decode_bool():
     tlw_invert, tlw_unpicks = decoder.decode_choices()
+    walk_squad = SquadAdvice(
+      tlw_unpicks[-1] if tlw_invert else NOBODY,
+      reduce(op_or, tlw_unpicks[:-1] if tlw_invert else tlw_unpicks, NOBODY))
   final_squad = decoder.decode_squad()
+  return DecodedTraversal(recruits, loyal, upgraded_armor, upgraded_shield,
+                          upgraded_weapon, cargo_bay_squad, tech, leader1,
+                          ideal_leaders, biotic, leader2, escort, walk_squad,
+                          final_squad)
+
+def describe_traversal(traversal: int) -> str:
+  """Produces a human-readable string describing the encoded traversal."""
+  decoded = decode_traversal(traversal)
+  recruits, loyal = decoded.recruits, decoded.loyalty
+  upgraded_armor = decoded.armor
+  upgraded_shield = decoded.shield
+  upgraded_weapon = decoded.weapon
 
   output = ""
   if upgraded_armor and upgraded_shield and upgraded_weapon:
@@ -86,50 +177,309 @@ def describe_traversal(traversal: int) -> str:
   else:
     output += f"Loyalty Missions: {loyal}\n"
   
-  if not upgraded_shield:
-    output += "For the cargo bay squad, "
-    cbs_take = reduce(op_or,
-      cbs_picks[:-1] if cbs_invert else cbs_picks, NOBODY)
-    cbs_leave = cbs_picks[-1] if cbs_invert else NOBODY
-    if cbs_take:
-      output += f"pick {cbs_take}"
-      if cbs_leave:
-        output += " and "
-    if cbs_leave:
-      output += f"make sure to leave {cbs_leave} behind"
-    output += ".\n"
+  if decoded.cargo_bay_squad is not None:
+    output += f"For the cargo bay squad, {decoded.cargo_bay_squad}.\n"
   
-  output += f"Choose {tech} as the tech specialist.\n"
-  if has_leader1:
-    output += "Choose "
-    if not leader1:
-      output += "anyone except "
-    output += f"{ideal_leaders.conj('or')} to lead the second fireteam.\n"
+  output += f"Choose {decoded.tech} as the tech specialist.\n"
+  first_leader_advice = decoded.first_leader_advice()
+  if first_leader_advice is not None:
+    output += f"Choose {first_leader_advice} to lead the second fireteam.\n"
   else:
     output += "The second fireteam leader does not matter.\n"
 
-  output += f"Choose {biotic} as the biotic specialist.\n"
-  output += f"Choose {leader2} to lead the diversion team.\n"
-  if escort:
-    output += f"Send {escort} to escort the crew.\n"
+  output += f"Choose {decoded.biotic} as the biotic specialist.\n"
+  output += f"Choose {decoded.second_leader} to lead the diversion team.\n"
+  if decoded.escort:
+    output += f"Send {decoded.escort} to escort the crew.\n"
   else:
     output += "Do not send anyone to escort the crew.\n"
-  if has_tlw_unpicks:
-    output += "For the squad in the biotic shield, "
-    tlw_take = tlw_unpicks[-1] if tlw_invert else NOBODY
-    tlw_leave = reduce(op_or,
-      tlw_unpicks[:-1] if tlw_invert else tlw_unpicks, NOBODY)
-    if tlw_take:
-      output += f"pick {tlw_take}"
-      if tlw_leave:
-        output += " and "
-    if tlw_leave:
-      output += f"make sure to leave {tlw_leave} behind"
-    output += ".\n"
+  if decoded.walk_squad is not None:
+    output += f"For the squad in the biotic shield, {decoded.walk_squad}.\n"
 
-  output += f"Pick {final_squad} for your final squad.\n"
+  output += f"Pick {decoded.final_squad} for your final squad.\n"
   return output
 
+# Human-readable names of the decisions compared by diff_traversals in the
+# order in which they are made
+_DECISION_NAMES = {
+  "recruits": "Recruits",
+  "loyalty": "Loyalty missions",
+  "armor": "Silaris Armor",
+  "shield": "Cyclonic Shields",
+  "weapon": "Thanix Cannon",
+  "cargo_bay_squad": "Cargo bay squad",
+  "tech": "Tech specialist",
+  "first_leader": "Second fireteam leader",
+  "biotic": "Biotic specialist",
+  "second_leader": "Diversion team leader",
+  "escort": "Crew escort",
+  "walk_squad": "Biotic shield squad",
+  "final_squad": "Final squad"
+}
+
+def _describe_decision(decoded: DecodedTraversal, name: str) -> str:
+  if name == "first_leader":
+    value: Any = decoded.first_leader_advice()
+  else:
+    value = getattr(decoded, name)
+  if value is None:
+    return "n/a"
+  if isinstance(value, bool):
+    return "yes" if value else "no"
+  return str(value) or "nobody"
+
+def diff_traversals(a: int, b: int) -> list[str]:
+  """Lists the decisions that differ between two encoded traversals as
+  human-readable lines like "Tech specialist: Legion -> Kasumi".
+
+  Identical traversals produce an empty list.
+  """
+  decoded_a = decode_traversal(a)
+  decoded_b = decode_traversal(b)
+  diff = []
+  for name, label in _DECISION_NAMES.items():
+    before = _describe_decision(decoded_a, name)
+    after = _describe_decision(decoded_b, name)
+    if before != after:
+      diff.append(f"{label}: {before} -> {after}")
+  return diff
+
+def _default_victim(team: int, priority: list[int]) -> int:
+  try:
+    return death.get_victim(team, priority)
+  except death.UnexpectedlyVictimlessError:
+    return NOBODY.value
+
+def _play_traversal(decoded: DecodedTraversal, rules: death.RuleSet,
+                    violation: Callable[[str, str], None]) -> Optional[int]:
+  """Plays out the decoded traversal under the given rules and returns the
+  encoded outcome.
+
+  Each decision that DecisionTree would never make is reported to violation
+  with the name of the decision and a message, and playing continues past it.
+  If anything was reported, the outcome is meaningless, so None is returned.
+  """
+  valid = True
+  def report(name: str, message: str):
+    nonlocal valid
+    valid = False
+    violation(name, message)
+
+  # Morinth is always loyal.
+  loyal = (decoded.loyalty | Ally.Morinth).value
+  team = decoded.team.value
+  if team & Ally.Morinth.value and not Ally.Samara.value & loyal:
+    report("recruits", "Morinth requires Samara's loyalty mission")
+  if bits.popcount(team & OPTIONAL.value) < 3:
+    report("recruits", f"At least three optional allies are required, but "
+                       f"only {Ally(team & OPTIONAL.value)} remain")
+  unrecruited = decoded.loyalty & LOYALTY_MASK & ~(decoded.recruits | REQUIRED)
+  if unrecruited:
+    report("loyalty", f"{unrecruited} not recruited")
+
+  # Each missing upgrade kills one ally in sequence. Allies taken into the
+  # cargo bay squad cannot die to the shields.
+  take, leave = (ally.value for ally in decoded.cargo_bay_squad
+                 or SquadAdvice(NOBODY, NOBODY))
+  shield_priority = [a for a in rules.no_shield_upgrade if not a & take]
+  armor_victim, shield_victim, weapon_victim = death.get_victims(team, [
+    [] if decoded.armor else rules.no_armor_upgrade,
+    [] if decoded.shield else shield_priority,
+    [] if decoded.weapon else rules.no_weapon_upgrade
+  ])
+  if take & ~(team & ~armor_victim):
+    report("cargo_bay_squad",
+           f"{Ally(take & ~(team & ~armor_victim))} not on the team")
+  # Only two candidates are encoded. If nobody is left behind, the victim is
+  # the next candidate after the squad.
+  if leave and leave != shield_victim:
+    report("cargo_bay_squad",
+           f"{Ally(leave)} is not the next victim after the squad")
+  team &= ~(armor_victim | shield_victim | weapon_victim)
+
+  tech = decoded.tech.value
+  if not tech & team & TECHS.value:
+    report("tech", f"{decoded.tech or 'Nobody'} cannot be the tech specialist")
+  if decoded.first_leader is not None:
+    ideal_leaders = Ally(team & ~tech & loyal & IDEAL_LEADERS.value)
+    if decoded.ideal_leaders != ideal_leaders:
+      report("first_leader",
+             f"The ideal leaders are {ideal_leaders or 'Nobody'}, not "
+             f"{decoded.ideal_leaders or 'Nobody'}")
+    elif decoded.first_leader and not ideal_leaders:
+      report("first_leader", "No ideal leader remains to protect the tech")
+  if not (tech & loyal & IDEAL_TECHS.value and decoded.first_leader):
+    team &= ~tech
+
+  biotic = decoded.biotic.value
+  leader = decoded.second_leader.value
+  if not biotic & team & BIOTICS.value:
+    report("biotic", f"{decoded.biotic or 'Nobody'} cannot be the biotic "
+                     f"specialist")
+  if not leader & team & ~biotic:
+    report("second_leader", f"{decoded.second_leader or 'Nobody'} cannot lead "
+                            f"the diversion team")
+
+  escort = decoded.escort.value
+  if escort:
+    if not escort & team & ESCORTS.value & ~(biotic | leader):
+      report("escort", f"{decoded.escort} cannot escort the crew")
+    if bits.popcount(team) <= 4:
+      report("escort", "Too few allies remain to escort the crew")
+    team &= ~escort
+
+  if not biotic & loyal & IDEAL_BIOTICS.value:
+    victim_pool = team & ~(biotic | leader)
+    victim = NOBODY.value
+    if decoded.walk_squad is not None:
+      # Only two candidates are encoded. If no candidate is picked, the victim
+      # is the next candidate after those left behind.
+      victim, leave = (ally.value for ally in decoded.walk_squad)
+      if (victim | leave) & ~victim_pool:
+        report("walk_squad",
+               f"{Ally((victim | leave) & ~victim_pool)} not available")
+      victim_pool &= ~leave
+    default = _default_victim(victim_pool, rules.the_long_walk)
+    if victim and victim != default:
+      report("walk_squad", f"{Ally(victim)} is not the next victim after those "
+                           f"left behind")
+    team &= ~default
+  elif decoded.walk_squad is not None:
+    report("walk_squad", "The biotic specialist protects everyone")
+
+  alive = bool(leader & loyal & IDEAL_LEADERS.value)
+  alive = alive or bool(leader & IMMORTAL_LEADERS.value)
+  if not (alive or bits.popcount(team) < 4):
+    team &= ~leader
+  squad = decoded.final_squad
+  if len(squad) != 2:
+    report("final_squad", f"{squad or 'Nobody'} is not two allies")
+  if squad & ~Ally(team):
+    report("final_squad", f"{squad & ~Ally(team)} not available")
+  if not valid:
+    return None
+
+  victims = death.get_defense_victims(team & ~squad.value, loyal, rules)
+  victims |= squad.value & ~loyal
+  spared = team & ~victims | escort & loyal
+  return encdec.encode_outcome(spared, spared & loyal, bool(escort))
+
+def validate_traversal(traversal: int,
+                       rules: death.RuleSet = death.DEFAULT_RULES) -> list[str]:
+  """Lists every decision of the encoded traversal that DecisionTree would
+  never make as human-readable lines like "Tech specialist: Kasumi is not on
+  the team".
+
+  Deaths are played out under the given rules, and checking continues past
+  each violation, so one mistake may lead to others. A traversal generated by
+  DecisionTree produces an empty list.
+  """
+  violations: list[str] = []
+  def violation(name: str, message: str):
+    violations.append(f"{_DECISION_NAMES[name]}: {message}")
+  _play_traversal(decode_traversal(traversal), rules, violation)
+  return violations
+
+def evaluate_traversal(traversal: int,
+                       rules: death.RuleSet = death.DEFAULT_RULES) -> int:
+  """Plays out the decisions of the encoded traversal under the given rules and
+  returns the encoded outcome.
+
+  This follows the same logic as DecisionTree, so the outcome of every recorded
+  traversal is the outcome that it was recorded under. Raises a ValueError for
+  the first decision that validate_traversal() would report.
+  """
+  def violation(name: str, message: str):
+    raise ValueError(f"{_DECISION_NAMES[name]}: {message}")
+  outcome = _play_traversal(decode_traversal(traversal), rules, violation)
+  assert outcome is not None
+  return outcome
+
+class Reconciliation(NamedTuple):
+  """Compares the predicted outcome of a claimed traversal with an observed
+  outcome.
+
+  Each suggestion names a decision that, if it was not made as claimed, could
+  explain some of the differences, followed by who it affects in parentheses.
+  A consistent pair has no differences and no suggestions.
+  """
+  predicted: int
+  observed: int
+  differences: list[str]
+  suggestions: list[str]
+
+  def __str__(self) -> str:
+    if not self.differences:
+      return "The observed outcome matches the prediction."
+    output  = f"Predicted: {describe_outcome(self.predicted, brief=True)}\n"
+    output += f"Observed:  {describe_outcome(self.observed, brief=True)}\n"
+    output += "".join(f"{line}\n" for line in self.differences)
+    if not self.suggestions:
+      return output + "No single decision explains the differences."
+    output += "Decisions that could explain the differences:"
+    return output + "".join(f"\n  {line}" for line in self.suggestions)
+
+# The decisions that can keep an ally from being the victim of each
+# single-victim priority list
+_PRIORITY_DECISIONS = {
+  "no_armor_upgrade": "armor",
+  "no_shield_upgrade": "shield",
+  "no_weapon_upgrade": "weapon",
+  "the_long_walk": "biotic"
+}
+
+def reconcile(claimed: int, observed: int,
+              rules: death.RuleSet = death.DEFAULT_RULES) -> Reconciliation:
+  """Evaluates the claimed traversal and reconciles its predicted outcome with
+  the encoded observed outcome.
+
+  Deaths are attributed to decisions with death.diagnose(), so, like it, this
+  only considers the default victims of each priority list. Raises a ValueError
+  if the claimed traversal is invalid.
+  """
+  predicted = evaluate_traversal(claimed, rules)
+  decoded = decode_traversal(claimed)
+  before = encdec.decode_outcome(predicted)
+  after = encdec.decode_outcome(observed)
+  team = decoded.team
+  loyal = decoded.loyalty | Ally.Morinth
+  # Whether the claimed decisions keep the default victims alive
+  safe = {
+    "armor": decoded.armor,
+    "shield": decoded.shield,
+    "weapon": decoded.weapon,
+    "biotic": bool(decoded.biotic & loyal & IDEAL_BIOTICS)
+  }
+
+  affected: dict[str, list[str]] = {}
+  def suggest(decision: str, who: str):
+    affected.setdefault(decision, []).append(who)
+
+  for ally in after.spared & ~before.spared:
+    if not ally & team:
+      suggest("recruits", ally.name)
+      continue
+    if not ally & loyal:
+      suggest("loyalty", ally.name)
+    for name in death.diagnose(ally.value, team.value, rules):
+      if not safe[_PRIORITY_DECISIONS[name]]:
+        suggest(_PRIORITY_DECISIONS[name], ally.name)
+  for ally in before.spared & ~after.spared:
+    if ally & loyal:
+      suggest("loyalty", ally.name)
+    for name in death.diagnose(ally.value, team.value, rules):
+      if safe[_PRIORITY_DECISIONS[name]]:
+        suggest(_PRIORITY_DECISIONS[name], ally.name)
+  for ally in (before.loyalty ^ after.loyalty) & before.spared & after.spared:
+    suggest("loyalty", ally.name)
+  if before.crew != after.crew:
+    suggest("escort", "the crew")
+
+  suggestions = [f"{label} ({', '.join(dict.fromkeys(affected[name]))})"
+                 for name, label in _DECISION_NAMES.items()
+                 if name in affected]
+  return Reconciliation(predicted, observed,
+                        diff_outcomes(predicted, observed), suggestions)
 
 class Checkpoint(enum.Enum):
   N_OPT = enum.auto()
@@ -161,9 +511,101 @@ class DecisionTreePauseException(Exception):
   pass
 
 
+class Constraints(NamedTuple):
+  """Restricts the traversals generated by a decision tree.
+
+  Branches that cannot satisfy the constraints are abandoned as soon as that is
+  known, so a constrained decision tree generates far fewer traversals. Use
+  _replace() to derive constraints from existing ones. A decision tree stores
+  _asdict() alongside its generated data, so a file can only be loaded with the
+  constraints that generated it.
+  """
+  # Allies who must survive the final mission.
+  spared: int = NOBODY.value
+  # The following decisions are pinned to the given values unless None.
+  # Optional allies to recruit, not including Morinth
+  recruits: Optional[int] = None
+  # Allies whose loyalty missions are completed
+  loyalty: Optional[int] = None
+  # Whether Morinth replaces Samara
+  morinth: Optional[bool] = None
+  armor: Optional[bool] = None
+  shield: Optional[bool] = None
+  weapon: Optional[bool] = None
+  # The ally who escorts the crew or NOBODY.value for no escort
+  escort: Optional[int] = None
+  # Numbers of optional allies that may be recruited, not including Morinth
+  recruit_counts: Optional[range] = None
+
+  def validate(self, rules: death.RuleSet = death.DEFAULT_RULES):
+    """Raises a ValueError if the constraints are inconsistent with each other
+    under the given rules."""
+    team = EVERYONE.value
+    if self.recruits is not None:
+      if self.recruits & ~RECRUITABLE.value:
+        raise ValueError(f"Not recruitable: {Ally(self.recruits)}")
+      if bits.popcount(self.recruits) < 3:
+        raise ValueError("At least three optional allies must be recruited")
+      team = self.recruits | REQUIRED.value
+    if self.recruit_counts is not None:
+      counts = self.recruit_counts
+      if not counts or min(counts) < 3 or max(counts) > len(RECRUITABLE):
+        raise ValueError(f"Recruit counts must be within 3 to "
+                         f"{len(RECRUITABLE)}: {counts}")
+      if (self.recruits is not None
+          and bits.popcount(self.recruits) not in self.recruit_counts):
+        raise ValueError("Pinned recruits do not match the recruit counts")
+    if self.loyalty is not None and self.loyalty & LOYALTY_MASK.value & ~team:
+      raise ValueError(f"Loyal but not recruited: {Ally(self.loyalty & ~team)}")
+    if self.morinth:
+      if not Ally.Samara.value & team:
+        raise ValueError("Morinth requires recruiting Samara")
+      if self.loyalty is not None and not Ally.Samara.value & self.loyalty:
+        raise ValueError("Morinth requires Samara's loyalty mission")
+      team = (team | Ally.Morinth.value) & ~Ally.Samara.value
+    elif self.morinth is not None:
+      team &= ~Ally.Morinth.value
+    if self.escort:
+      if bits.popcount(self.escort) != 1 or self.escort & ~ESCORTS.value:
+        raise ValueError(f"Not a valid escort: {Ally(self.escort)}")
+      if self.escort & ~team:
+        raise ValueError(f"Escort not recruited: {Ally(self.escort)}")
+    # Allies who must be spared must be able to survive the pinned decisions.
+    if self.spared & ~team:
+      raise ValueError(f"Spared but not recruited: {Ally(self.spared & ~team)}")
+    if self.recruit_counts is not None:
+      spared_recruits = self.spared
+      if spared_recruits & Ally.Morinth.value:
+        spared_recruits |= Ally.Samara.value
+      if (bits.popcount(spared_recruits & RECRUITABLE.value)
+          > max(self.recruit_counts)):
+        raise ValueError("Too many optional allies must be spared")
+    try:
+      death.validate_team(self.spared)
+    except death.InvalidTeamError:
+      raise ValueError("Samara and Morinth cannot both be spared")
+    # A required ally is always on the team when the armor check happens.
+    armor_victim = rules.no_armor_upgrade[0] & REQUIRED.value
+    if self.armor is False and armor_victim & self.spared:
+      raise ValueError(f"Without the armor upgrade, {Ally(armor_victim)} dies")
+    if (self.escort and self.loyalty is not None
+        and self.escort & self.spared & ~self.loyalty):
+      raise ValueError(f"A disloyal escort dies: {Ally(self.escort)}")
+
+
 # Interval in seconds between periodic saves.
 _SAVE_INTERVAL = 5 * 60
 
+def _options(constraints: Constraints, rules: death.RuleSet) -> dict[str, Any]:
+  """Describes the options of a decision tree for storage in its file.
+
+  The defense toll formulae are code, so they are not stored.
+  """
+  return {"constraints": constraints._asdict(),
+          "rules": death.export_tables(rules)}
+
+_DEFAULT_OPTIONS = _options(Constraints(), death.DEFAULT_RULES)
+
 class DecisionTree:
   """Generates outcomes and traversals for Mass Effect 2's final mission.
   
@@ -176,9 +618,16 @@ class DecisionTree:
   concern. To reduce it somewhat, decision sequences and outcomes are encoded
   into a compact format for storage.
   """
-  def __init__(self, file_path: str):
+  def __init__(self,
+               file_path: str,
+               rules: death.RuleSet = death.DEFAULT_RULES,
+               constraints: Constraints = Constraints()):
     """Constructs a decision tree and optionally loads data from the file at the
-    given file_path."""
+    given file_path.
+
+    The death priorities and defense formulae applied during generation are
+    taken from rules. Only traversals satisfying constraints are generated.
+    """
     if not file_path:
       raise ValueError("A decision tree file path must be provided")
     # Stores checkpoint data that is necessary for traversal encoding but not
@@ -193,6 +642,15 @@ class DecisionTree:
     # the outcome.
     self.outcomes: dict[int, tuple[int, int]] = {}
     self.pausing = False
+    self.rules = rules
+    constraints.validate(rules)
+    self.constraints = constraints
+    # The number of decision nodes visited since construction.
+    self.visited = 0
+    # The value of visited at which generation pauses, if any.
+    self.visit_limit: Optional[int] = None
+    # Whether the timeout of the current generation has run out.
+    self.timed_out = False
     self.load()
 
   #
@@ -211,12 +669,17 @@ class DecisionTree:
     outcome = encdec.encode_outcome(
       spared = team,
       loyalty = team & self.loyal,
-      crew = self.checkpoints.get(Checkpoint.CREW, False)
+      crew = self.checkpoints.get(Checkpoint.CREW, False),
+      validate = False
     )
 
     # The bit-width of the encoded traversal is variable. (min, max) = (48, 70)
     encoder = encdec.Encoder()
-    encoder.encode_ally_optional(self.checkpoints[Checkpoint.RECRUITS])
+    # Morinth is not a recruitment choice, but she must be decodable.
+    recruits = self.checkpoints[Checkpoint.RECRUITS]
+    if self.checkpoints.get(Checkpoint.MORINTH, False):
+      recruits |= Ally.Morinth.value
+    encoder.encode_ally_optional(recruits)
     encoder.encode_ally_loyalty(self.loyal)
     encoder.encode_bool(self.checkpoints.get(Checkpoint.ARMOR, True))
     shield = self.checkpoints.get(Checkpoint.SHIELD, True)
@@ -258,28 +721,47 @@ class DecisionTree:
     """Sets the value for the requested checkpoint and checks if the user
     requested a pause or if a periodic save was requested."""
     self.checkpoints[key] = value
-    if self.pausing:
+    self.visited += 1
+    if self.pausing or self.timed_out or (self.visit_limit is not None
+                                          and self.visited > self.visit_limit):
       raise DecisionTreePauseException()
     if self.needs_save:
       self.save()
       self.needs_save = False
 
   def save(self):
-    """Writes checkpoints and outcome data to a file."""
+    """Writes checkpoints, outcome data, and the options that generated them
+    to a file."""
     with open(self.file_path, "wb") as datafile:
       pickler = pickle.Pickler(datafile)
       pickler.dump(self.checkpoints)
       pickler.dump(self.outcomes)
+      pickler.dump(_options(self.constraints, self.rules))
 
   def load(self):
-    """Reads checkpoints and outcome data from a file."""
+    """Reads checkpoints and outcome data from a file.
+
+    Raises a ValueError if the file was generated with different constraints or
+    rules. Files written before the options were stored were generated with the
+    defaults.
+    """
     try:
       with open(self.file_path, "rb") as datafile:
         unpickler = pickle.Unpickler(datafile)
-        self.checkpoints = unpickler.load()
-        self.outcomes = unpickler.load()
+        checkpoints = unpickler.load()
+        outcomes = unpickler.load()
+        try:
+          options = unpickler.load()
+        except EOFError:
+          options = _DEFAULT_OPTIONS
     except FileNotFoundError:
-      pass
+      return
+    for name, value in _options(self.constraints, self.rules).items():
+      if options.get(name) != value:
+        raise ValueError(f"{self.file_path} was generated with different "
+                         f"{name}")
+    self.checkpoints = checkpoints
+    self.outcomes = outcomes
   
   #
   # Runtime
@@ -292,42 +774,90 @@ class DecisionTree:
     """
     self.pausing = True
 
+  def _time_out(self):
+    self.timed_out = True
+
   def is_complete(self) -> bool:
     """Checks if the decision tree has exhausted all possible traversals."""
     return self.checkpoints.get(Checkpoint.N_OPT, None) == 0
 
-  def generate(self):
-    """Generates decision tree outcomes.
+  def generate(self,
+               max_visits: Optional[int] = None,
+               timeout: Optional[float] = None) -> bool:
+    """Generates decision tree outcomes and returns whether the decision tree
+    is complete.
     
     If this is called in the main thread, it temporarily installs a SIGINT
     handler to gracefully pause the operation and save as much progress as
     possible. This method can also safely be called in a child thread, and
-    calling pause() will ensure the thread is joinable.
+    calling pause(), even before the thread starts generating, will ensure the
+    thread is joinable.
+
+    Generation also pauses after visiting max_visits decision nodes or after
+    timeout seconds, whichever comes first. The outcomes are then partial, and
+    calling this method again continues where it left off. Resuming revisits
+    the checkpointed decisions, so max_visits must exceed their number.
     """
     if self.is_complete():
-      return
+      return True
+    if max_visits is not None and max_visits <= len(Checkpoint):
+      raise ValueError(f"At least {len(Checkpoint) + 1} visits are required")
+    self.timed_out = False
+    self.visit_limit = None if max_visits is None else self.visited + max_visits
+    deadline = Timer(timeout, self._time_out) if timeout is not None else None
     # Pressing Ctrl-C gracefully pauses the operation.
     with util.SigintHandler(self.pause):
       # Set up a timer to periodically save progress.
       with util.PeriodicTimer(_SAVE_INTERVAL, self.request_save):
         # Start generating the decision tree.
         try:
+          if deadline is not None:
+            deadline.start()
           self._choose_recruitment()
         except DecisionTreePauseException:
-          pass  # Graceful pause
+          # Graceful pause. A pause() request has now been handled, so the next
+          # call can make progress again.
+          self.pausing = False
         finally:
+          if deadline is not None:
+            deadline.cancel()
           self.save()
+    return self.is_complete()
 
   #
   # Private Decision Methods
   #
 
+  def _allows(self, decision: str, value: Any) -> bool:
+    """Checks if the named decision is either unpinned or pinned to value."""
+    pinned = getattr(self.constraints, decision)
+    return pinned is None or pinned == value
+
+  def _recruits_unspared(self, recruits: int) -> bool:
+    """Checks if an ally who must be spared would not be recruited."""
+    spared = self.constraints.spared
+    # Morinth can only be recruited by recruiting Samara.
+    if spared & Ally.Morinth.value:
+      spared |= Ally.Samara.value
+    return bool(spared & RECRUITABLE.value & ~recruits)
+
+  def _recruits_unloyal(self, recruits: int) -> bool:
+    """Checks if an ally with a pinned loyalty mission would not be
+    recruited."""
+    pinned = self.constraints.loyalty
+    if pinned is None:
+      return False
+    return bool(pinned & LOYALTY_MASK.value & ~(recruits | REQUIRED.value))
+
   def _choose_recruitment(self):
     # At least three optional allies must be recruited to finish the game.
     n_start = self.checkpoints.get(Checkpoint.N_OPT, 3)
     if n_start == 0:
       return
+    counts = self.constraints.recruit_counts
     for n in range(n_start, len(RECRUITABLE) + 1):
+      if counts is not None and n not in counts:
+        continue
       self.set_checkpoint(Checkpoint.N_OPT, n)
       self._choose_recruits(n)
     # Signal that all outcomes have been generated.
@@ -342,9 +872,13 @@ class DecisionTree:
       if ckpt_recruits and recruits != ckpt_recruits:
         continue
       ckpt_recruits = NOBODY.value
+      if not self._allows("recruits", recruits):
+        continue
+      if self._recruits_unspared(recruits) or self._recruits_unloyal(recruits):
+        continue
       self.set_checkpoint(Checkpoint.RECRUITS, recruits)
       self._choose_loyalty_missions(recruits | REQUIRED.value)
-    del self.checkpoints[Checkpoint.RECRUITS]
+    self.checkpoints.pop(Checkpoint.RECRUITS, None)
 
   def _choose_loyalty_missions(self, team: int):
     # Iterate through all relevant loyalty mappings. Morinth is always loyal.
@@ -356,41 +890,55 @@ class DecisionTree:
       if self.loyal & LOYALTY_MASK.value & ~team:
         loyalty += bits.fsb(loyalty)
         continue
+      pinned = self.constraints.loyalty
+      if pinned is not None and (loyalty ^ pinned) & LOYALTY_MASK.value:
+        loyalty += 1
+        continue
       self.set_checkpoint(Checkpoint.LOYALTY, loyalty)
       self._choose_morinth(team)
       # Increment loop variable.
       loyalty += 1
-    del self.checkpoints[Checkpoint.LOYALTY]
+    self.checkpoints.pop(Checkpoint.LOYALTY, None)
   
   def _choose_morinth(self, team: int):
-    if not self.checkpoints.get(Checkpoint.MORINTH, False):
+    spared = self.constraints.spared
+    if not (self.checkpoints.get(Checkpoint.MORINTH, False)
+            or spared & Ally.Morinth.value) and self._allows("morinth", False):
       self._choose_armor_upgrade(team)
     # If Samara was recruited and loyal, re-run with Morinth instead.
-    # Recruiting Morinth always kills Samara.
-    if Ally.Samara.value & team & self.loyal:
+    # Recruiting Morinth always kills Samara, so no team below contains both,
+    # and the death and outcome calls below skip validating it.
+    if (Ally.Samara.value & team & self.loyal & ~spared
+        and self._allows("morinth", True)):
       self.set_checkpoint(Checkpoint.MORINTH, True)
-      self._choose_armor_upgrade(team | Ally.Morinth.value & ~Ally.Samara.value)
+      team = (team | Ally.Morinth.value) & ~Ally.Samara.value
+      self._choose_armor_upgrade(team)
     self.checkpoints.pop(Checkpoint.MORINTH, None)
 
   def _choose_armor_upgrade(self, team: int):
     # If you upgrade to Silaris Armor, no one dies.
-    if self.checkpoints.get(Checkpoint.ARMOR, True):
+    if (self.checkpoints.get(Checkpoint.ARMOR, True)
+        and self._allows("armor", True)):
       self._choose_shield_upgrade(team)
     # Otherwise, there is a victim.
-    self.set_checkpoint(Checkpoint.ARMOR, False)
-    victim = death.get_victim(team, death.DP_NO_ARMOR_UPGRADE)
-    self._choose_shield_upgrade(team & ~victim)
-    del self.checkpoints[Checkpoint.ARMOR]
+    victim = death.get_victim(team, self.rules.no_armor_upgrade,
+                              validate=False)
+    if not victim & self.constraints.spared and self._allows("armor", False):
+      self.set_checkpoint(Checkpoint.ARMOR, False)
+      self._choose_shield_upgrade(team & ~victim)
+    self.checkpoints.pop(Checkpoint.ARMOR, None)
 
   def _choose_shield_upgrade(self, team: int):
     # If you upgrade to Cyclonic Shields, no one dies.
-    if self.checkpoints.get(Checkpoint.SHIELD, True):
+    if (self.checkpoints.get(Checkpoint.SHIELD, True)
+        and self._allows("shield", True)):
       self._choose_weapon_upgrade(team)
     # Otherwise, there is a victim, but you can affect who it is through your
     # squad selection for the battle in the cargo bay.
-    self.set_checkpoint(Checkpoint.SHIELD, False)
-    self._choose_cargo_bay_squad(team)
-    del self.checkpoints[Checkpoint.SHIELD]
+    if self._allows("shield", False):
+      self.set_checkpoint(Checkpoint.SHIELD, False)
+      self._choose_cargo_bay_squad(team)
+    self.checkpoints.pop(Checkpoint.SHIELD, None)
 
   def _choose_cargo_bay_squad(self, team: int):
     ckpt_pick = self.checkpoints.get(Checkpoint.CB_PICK, NOBODY.value)
@@ -400,41 +948,46 @@ class DecisionTree:
     victim_pool = team
     picks: list[int] = []
     for pick in range(3):
-      victim = death.get_victim(victim_pool, death.DP_NO_SHIELD_UPGRADE)
+      victim = death.get_victim(victim_pool, self.rules.no_shield_upgrade,
+                                validate=False)
       picks.append(victim)
-      if pick >= ckpt_pick:
+      if pick >= ckpt_pick and not victim & self.constraints.spared:
         self.set_checkpoint(Checkpoint.CB_PICK, pick)
         self.cache[CacheKey.CARGO_BAY_PICKS] = picks
         self._choose_weapon_upgrade(team & ~victim)
       # Selecting the prioritized victim(s) for your squad removes them from the
       # victim pool.
       victim_pool &= ~victim
-    del self.cache[CacheKey.CARGO_BAY_PICKS]
-    del self.checkpoints[Checkpoint.CB_PICK]
+    self.cache.pop(CacheKey.CARGO_BAY_PICKS, None)
+    self.checkpoints.pop(Checkpoint.CB_PICK, None)
 
   def _choose_weapon_upgrade(self, team: int):
     # If you upgrade to the Thanix Cannon, no one dies.
-    if self.checkpoints.get(Checkpoint.WEAPON, True):
+    if (self.checkpoints.get(Checkpoint.WEAPON, True)
+        and self._allows("weapon", True)):
       self._choose_tech(team)
     # Otherwise, there is a victim.
-    self.set_checkpoint(Checkpoint.WEAPON, False)
-    victim = death.get_victim(team, death.DP_NO_WEAPON_UPGRADE)
-    self._choose_tech(team & ~victim)
-    del self.checkpoints[Checkpoint.WEAPON]
+    victim = death.get_victim(team, self.rules.no_weapon_upgrade,
+                              validate=False)
+    if not victim & self.constraints.spared and self._allows("weapon", False):
+      self.set_checkpoint(Checkpoint.WEAPON, False)
+      self._choose_tech(team & ~victim)
+    self.checkpoints.pop(Checkpoint.WEAPON, None)
   
   def _choose_tech(self, team: int):
     # Iterate through all selectable teammates for the tech specialist.
     cur_tech = self.checkpoints.get(Checkpoint.TECH, NOBODY.value)
     for tech in bits.bits(team & TECHS.value & ~bits.mtz(cur_tech)):
-      self.set_checkpoint(Checkpoint.TECH, tech)
       # If the tech specialist is loyal and ideal, their survival depends on the
       # first fireteam leader.
       if tech & self.loyal & IDEAL_TECHS.value:
+        self.set_checkpoint(Checkpoint.TECH, tech)
         self._choose_first_leader(team, tech)
-      else:
+      elif not tech & self.constraints.spared:
         # Otherwise, they will die. The first fireteam leader does not matter.
+        self.set_checkpoint(Checkpoint.TECH, tech)
         self._choose_biotic(team & ~tech)
-    del self.checkpoints[Checkpoint.TECH]
+    self.checkpoints.pop(Checkpoint.TECH, None)
 
   def _choose_first_leader(self, team: int, tech: int):
     # Check if we have any ideal leaders.
@@ -445,10 +998,11 @@ class DecisionTree:
       # If the leader is loyal and ideal, the tech will be spared.
       self._choose_biotic(team)
     # Otherwise, the tech will die.
-    self.set_checkpoint(Checkpoint.LEADER1, False)
-    self._choose_biotic(team & ~tech)
+    if not tech & self.constraints.spared:
+      self.set_checkpoint(Checkpoint.LEADER1, False)
+      self._choose_biotic(team & ~tech)
     del self.cache[CacheKey.IDEAL_LEADERS]
-    del self.checkpoints[Checkpoint.LEADER1]
+    self.checkpoints.pop(Checkpoint.LEADER1, None)
 
   def _choose_biotic(self, team: int):
     # Iterate through all selectable teammates for the biotic specialist.
@@ -471,9 +1025,10 @@ class DecisionTree:
     # NOTE: If only four teammates (the minimum possible) remain at this point,
     # then an escort cannot be selected, since Shepard must have two squadmates
     # for The Long Walk.
-    if not self.checkpoints.get(Checkpoint.CREW, False):
+    if (not self.checkpoints.get(Checkpoint.CREW, False)
+        and self._allows("escort", NOBODY.value)):
       self._choose_walk_squad(team, biotic, leader)
-    if bits.popcount(team) > 4:
+    if bits.popcount(team) > 4 and self.constraints.escort != NOBODY.value:
       # Escorting the crew will save them.
       self.set_checkpoint(Checkpoint.CREW, True)
       self._choose_escort(team, biotic, leader)
@@ -486,11 +1041,15 @@ class DecisionTree:
     remaining_escorts = team & ESCORTS.value
     remaining_escorts &= ~(biotic | leader | bits.mtz(cur_escort))
     for escort in bits.bits(remaining_escorts):
+      if escort & ~self.loyal & self.constraints.spared:
+        continue
+      if not self._allows("escort", escort):
+        continue
       self.set_checkpoint(Checkpoint.ESCORT, escort)
       # The escort is removed from the team, but they survive if they are loyal.
       # That logic is handled in record_outcome().
       self._choose_walk_squad(team & ~escort, biotic, leader)
-    del self.checkpoints[Checkpoint.ESCORT]
+    self.checkpoints.pop(Checkpoint.ESCORT, None)
   
   def _choose_walk_squad(self, team: int, biotic: int, leader: int):
     # If the biotic specialist is loyal and ideal, they will not get anyone on
@@ -501,7 +1060,10 @@ class DecisionTree:
     # only one possible outcome.
     victim_pool = team & ~(biotic | leader)
     if bits.popcount(victim_pool) < 3:
-      victim = death.get_victim(victim_pool, death.DP_THE_LONG_WALK)
+      victim = death.get_victim(victim_pool, self.rules.the_long_walk,
+                                validate=False)
+      if victim & self.constraints.spared:
+        return
       return self._choose_final_squad(team & ~victim, leader)
     # Otherwise, you may be able to affect who the victim is through your squad
     # selection.
@@ -509,16 +1071,17 @@ class DecisionTree:
     unpicks: list[int] = []
     self.cache[CacheKey.LONG_WALK_UNPICKS] = unpicks
     for unpick in range(min(bits.popcount(victim_pool) - 1, 3)):
-      victim = death.get_victim(victim_pool, death.DP_THE_LONG_WALK)
+      victim = death.get_victim(victim_pool, self.rules.the_long_walk,
+                                validate=False)
       unpicks.append(victim)
-      if unpick >= ckpt_unpick:
+      if unpick >= ckpt_unpick and not victim & self.constraints.spared:
         self.set_checkpoint(Checkpoint.WALK_UNPICK, unpick)
         self._choose_final_squad(team & ~victim, leader)
       # *Not* selecting the prioritized victim(s) removes them from the victim
       # pool.
       victim_pool &= ~victim
     del self.cache[CacheKey.LONG_WALK_UNPICKS]
-    del self.checkpoints[Checkpoint.WALK_UNPICK]
+    self.checkpoints.pop(Checkpoint.WALK_UNPICK, None)
 
   def _choose_final_squad(self, team: int, leader: int):
     # The leader of the second fireteam will not die under several conditions:
@@ -528,6 +1091,8 @@ class DecisionTree:
     alive = bool(leader & self.loyal & IDEAL_LEADERS.value)
     alive = alive or bool(leader & IMMORTAL_LEADERS.value)
     if not (alive or bits.popcount(team) < 4):
+      if leader & self.constraints.spared:
+        return
       team &= ~leader
     # Iterate through all possible final squads.
     ckpt_squad = self.checkpoints.get(Checkpoint.FINAL_SQUAD, NOBODY.value)
@@ -539,9 +1104,13 @@ class DecisionTree:
       ckpt_squad = NOBODY.value
       self.set_checkpoint(Checkpoint.FINAL_SQUAD, squad)
       # The remaining active teammates form the defense team.
-      victims = death.get_defense_victims(team & ~squad, self.loyal)
+      defense = team & ~squad
+      victims = death.get_defense_victims(defense, self.loyal, self.rules,
+                                          validate=False)
       # Any member of your squad that is not loyal will die.
       victims |= squad & ~self.loyal
+      if victims & self.constraints.spared:
+        continue
       # Any active teammates at this point have survived.
       self.record_outcome(team & ~victims)
-    del self.checkpoints[Checkpoint.FINAL_SQUAD]
\ No newline at end of file
+    self.checkpoints.pop(Checkpoint.FINAL_SQUAD, None)
\ No newline at end of file
@@ -6,7 +6,7 @@
 
 from typing import NamedTuple
 
-from . import ally, bits
+from . import ally, bits, death
 from .ally import Ally
 
 _ALLY_LEN = len(Ally)
@@ -16,6 +16,7 @@ _ALLY_OPTIONAL_SHIFT = bits.ffs(ally.OPTIONAL.value)
 _ALLY_INDEX_LEN = _ALLY_LEN.bit_length()
 _ALLY_INDEX_MASK = bits.mask(_ALLY_INDEX_LEN)
 _IDEAL_LEADERS_LEN = len(ally.IDEAL_LEADERS)
+_OUTCOME_LEN = _ALLY_LEN + _ALLY_LOYALTY_LEN + 1
 
 class Encoder:
   """Facilitates bit-packing various encodings in a variable sequence."""
@@ -94,8 +95,20 @@ class Encoder:
     self.encode_ally_value_as_index(second_choice)
 
 
-def encode_outcome(spared: int, loyalty: int, crew: bool) -> int:
-  """Encodes outcome data as an int."""
+def encode_outcome(spared: int,
+                   loyalty: int,
+                   crew: bool,
+                   *,
+                   validate: bool = True) -> int:
+  """Encodes outcome data as an int.
+
+  Raises a ValueError if spared is not a valid team. Set validate to False to
+  skip the check for a team that is valid by construction.
+  """
+  if validate:
+    if spared & ~ally.EVERYONE.value:
+      raise ValueError(f"Not a team of allies: {spared:#x}")
+    death.validate_team(spared)
   encoder = Encoder()
   encoder.encode_ally_value(spared)
   # The loyalty of dead allies does not affect the outcome.
@@ -163,15 +176,41 @@ class Decoder:
 
 
 class DecodedOutcome(NamedTuple):
+  """The survivors of the final mission.
+
+  Since the loyalty of dead allies is not encoded, loyalty is always a subset
+  of spared. Required allies can die, so spared does not necessarily include
+  them. Morinth is loyal whenever she survives.
+
+  >>> outcome = encode_outcome((Ally.Grunt | Ally.Jack).value,
+  ...                          (Ally.Grunt | Ally.Tali).value, True)
+  >>> decoded = decode_outcome(outcome)
+  >>> str(decoded.spared), str(decoded.loyalty), decoded.crew
+  ('Grunt and Jack', 'Grunt', True)
+  """
+  # Allies who survived
   spared: Ally
+  # Surviving allies whose loyalty missions were completed
   loyalty: Ally
+  # Whether the surviving crew was rescued
   crew: bool
 
+  @property
+  def shepard(self) -> bool:
+    """Whether Shepard survived, i.e., whether two or more allies did."""
+    return len(self.spared) >= 2
+
 
 def decode_outcome(encoded: int) -> DecodedOutcome:
-  """Decodes outcome data from an int."""
+  """Decodes outcome data from an int.
+
+  Raises a ValueError if encoded is not a valid outcome encoding.
+  """
+  if encoded < 0 or encoded >> _OUTCOME_LEN:
+    raise ValueError(f"Not an encoded outcome: {encoded:#x}")
   decoder = Decoder(encoded)
   spared = decoder.decode_ally()
+  death.validate_team(spared.value)
   loyalty = decoder.decode_ally_loyalty() | Ally.Morinth & spared
   crew = decoder.decode_bool()
   return DecodedOutcome(spared, loyalty, crew)
\ No newline at end of file
@@ -0,0 +1,326 @@
+#
+# Copyright (c) 2022 Andrew Lehmer
+#
+# Distributed under the MIT License.
+#
+
+from __future__ import annotations
+from collections.abc import Callable, Iterable, Mapping
+import csv
+import enum
+from fractions import Fraction
+import random
+import sqlite3
+from typing import Any, NamedTuple, Optional, TextIO
+
+from .ally import Ally, EVERYONE
+from .dt import decode_traversal, describe_outcome, describe_traversal
+from .encdec import DecodedOutcome, decode_outcome, encode_outcome
+
+# An item of the outcome dictionary of a DecisionTree
+OutcomeItem = tuple[int, tuple[int, int]]
+
+def find(outcomes: Iterable[OutcomeItem],
+         predicate: Callable[[DecodedOutcome], bool]) -> Optional[OutcomeItem]:
+  """Returns the first outcome item whose decoded outcome satisfies predicate
+  or None if there is no such item.
+
+  >>> find({0x1: (1, 42), 0x3: (2, 43)}.items(), lambda o: len(o.spared) > 1)
+  (3, (2, 43))
+  """
+  return next((item for item in outcomes if predicate(decode_outcome(item[0]))),
+              None)
+
+Predicate = Callable[[DecodedOutcome], bool]
+# An event and the condition under which its probability is measured
+Query = tuple[Predicate, Predicate]
+
+def fractions(outcomes: Iterable[OutcomeItem],
+              queries: Iterable[Query]) -> list[Fraction]:
+  """Answers several queries in one pass over the outcome items.
+
+  For each (event, given) pair of predicates, computes the exact fraction of
+  traversals with outcomes satisfying given whose outcomes also satisfy event.
+  Raises a ZeroDivisionError if no traversal satisfies a condition.
+  """
+  queries = list(queries)
+  events = [0] * len(queries)
+  conditions = [0] * len(queries)
+  for outcome, (count, _) in outcomes:
+    decoded = decode_outcome(outcome)
+    for i, (event, given) in enumerate(queries):
+      if given(decoded):
+        conditions[i] += count
+        if event(decoded):
+          events[i] += count
+  return [Fraction(e, c) for e, c in zip(events, conditions)]
+
+def fraction(outcomes: Iterable[OutcomeItem], event: Predicate,
+             given: Predicate = lambda _: True) -> Fraction:
+  """Computes the exact fraction of traversals with outcomes satisfying given
+  whose outcomes also satisfy event.
+
+  >>> fraction({0x1: (1, 42), 0x3: (2, 43)}.items(), lambda o: o.shepard)
+  Fraction(2, 3)
+  """
+  return fractions(outcomes, [(event, given)])[0]
+
+def merge(*outcome_maps: Mapping[int, tuple[int, int]]
+          ) -> dict[int, tuple[int, int]]:
+  """Combines outcome dictionaries of disjoint parts of the decision tree, such
+  as those generated under different Constraints.
+
+  Traversal counts are summed, and the encoded traversal of each outcome is
+  taken from the last dictionary that has it.
+
+  >>> merge({0x1: (1, 42)}, {0x1: (2, 43), 0x3: (1, 44)})
+  {1: (3, 43), 3: (1, 44)}
+  """
+  merged: dict[int, tuple[int, int]] = {}
+  for outcome_map in outcome_maps:
+    for outcome, (count, traversal) in outcome_map.items():
+      merged[outcome] = (merged.get(outcome, (0, 0))[0] + count, traversal)
+  return merged
+
+def rollup(outcomes: Iterable[OutcomeItem], *, loyalty: bool = False,
+           crew: bool = False) -> dict[int, tuple[int, int]]:
+  """Groups outcome items by their spared allies and, optionally, by their
+  loyalty and crew survival.
+
+  The result is an outcome dictionary whose keys are encoded without the
+  dropped parts of the outcome, so it can be rolled up again. Traversal counts
+  are summed, and the encoded traversal of each group is that of its last item.
+  """
+  grouped: dict[int, tuple[int, int]] = {}
+  for outcome, (count, traversal) in outcomes:
+    decoded = decode_outcome(outcome)
+    key = encode_outcome(decoded.spared.value,
+                         decoded.loyalty.value if loyalty else 0,
+                         decoded.crew and crew)
+    grouped[key] = (grouped.get(key, (0, 0))[0] + count, traversal)
+  return grouped
+
+def sample(outcomes: Iterable[OutcomeItem], k: int,
+           rng: Optional[random.Random] = None) -> list[OutcomeItem]:
+  """Draws k outcome items with replacement, weighting each by its traversal
+  count.
+
+  This is equivalent to taking the outcomes of k traversals chosen uniformly at
+  random from the decision tree. Pass a seeded rng for reproducible samples.
+
+  >>> sample({0x1: (0, 42), 0x3: (2, 43)}.items(), 2)
+  [(3, (2, 43)), (3, (2, 43))]
+  """
+  items = list(outcomes)
+  weights = [count for _, (count, _) in items]
+  return (rng or random).choices(items, weights, k=k)
+
+def deaths(item: OutcomeItem) -> Ally:
+  """Returns the allies who died in the encoded traversal of the outcome item.
+
+  Allies who were never recruited are not counted, so other traversals with the
+  same outcome may have different deaths.
+  """
+  outcome, (_, traversal) = item
+  return decode_traversal(traversal).team & ~decode_outcome(outcome).spared
+
+def rank(outcome: DecodedOutcome) -> tuple[int, bool, int]:
+  """Returns a sort key under which better outcomes compare greater.
+
+  Outcomes are ranked by the number of surviving allies, then by whether the
+  crew survived, and then by the number of loyal survivors. Shepard survives if
+  at least two allies do, so Shepard never fares worse in a better outcome.
+  """
+  return len(outcome.spared), outcome.crew, len(outcome.loyalty)
+
+def best(outcomes: Iterable[OutcomeItem]) -> Optional[OutcomeItem]:
+  """Returns the outcome item with the greatest rank or None if there are no
+  outcomes. Ties go to the first such item.
+
+  >>> best({0x1: (1, 42), 0x3: (2, 43), 0x5: (3, 44)}.items())
+  (3, (2, 43))
+  """
+  return max(outcomes, key=lambda item: rank(decode_outcome(item[0])),
+             default=None)
+
+def worst(outcomes: Iterable[OutcomeItem]) -> Optional[OutcomeItem]:
+  """Returns the outcome item with the least rank or None if there are no
+  outcomes. Ties go to the first such item.
+
+  >>> worst({0x3: (1, 42), 0x1: (2, 43), 0x2: (3, 44)}.items())
+  (1, (2, 43))
+  """
+  return min(outcomes, key=lambda item: rank(decode_outcome(item[0])),
+             default=None)
+
+def dominates(a: DecodedOutcome, b: DecodedOutcome) -> bool:
+  """Checks if outcome a is at least as good as outcome b in every respect:
+  everyone spared in b is spared in a, and the crew and Shepard fare no worse.
+
+  Unlike rank(), this is a partial order, so neither of two outcomes may
+  dominate the other. Every outcome dominates itself.
+  """
+  return (not b.spared & ~a.spared and a.crew >= b.crew
+          and a.shepard >= b.shepard)
+
+def is_perfect(outcome: DecodedOutcome, recruited: Ally) -> bool:
+  """Checks if the crew and every recruited ally survived the outcome.
+
+  The recruited allies include the required ones, as in the team of a decoded
+  traversal. An outcome is achieved by traversals with different recruits, so
+  they must be given rather than taken from the stored traversal.
+  """
+  return outcome.crew and not recruited & ~outcome.spared
+
+class OutcomeSummary(NamedTuple):
+  """Summarizes the outcomes of a set of traversals."""
+  traversals: int
+  # The number of traversals in which each ally did not survive, including
+  # those in which they were not recruited
+  deaths: dict[Ally, int]
+  best: Optional[OutcomeItem]
+  worst: Optional[OutcomeItem]
+
+def summarize(outcomes: Iterable[OutcomeItem]) -> OutcomeSummary:
+  """Summarizes the outcome items."""
+  items = list(outcomes)
+  deaths = dict.fromkeys(EVERYONE, 0)
+  for outcome, (count, _) in items:
+    for ally in EVERYONE & ~decode_outcome(outcome).spared:
+      deaths[ally] += count
+  return OutcomeSummary(sum(count for _, (count, _) in items), deaths,
+                        best(items), worst(items))
+
+def by_decision(shards: Mapping[Any, Mapping[int, tuple[int, int]]]
+                ) -> dict[Any, OutcomeSummary]:
+  """Summarizes the outcome dictionaries of a decision tree sharded by the
+  value of a decision, such as those generated under Constraints pinning the
+  decision to each of its values.
+  """
+  return {value: summarize(outcomes.items())
+          for value, outcomes in shards.items()}
+
+def describe_comparison(summaries: Mapping[Any, OutcomeSummary]) -> str:
+  """Produces a human-readable table comparing the summaries by column.
+
+  Deaths are given as percentages of the traversals in each column, and allies
+  who never survive are omitted.
+  """
+  def brief(item: Optional[OutcomeItem]) -> str:
+    return describe_outcome(item[0], brief=True) if item else "n/a"
+
+  rows = [["", *map(str, summaries)],
+          ["Traversals", *(str(s.traversals) for s in summaries.values())]]
+  for ally in EVERYONE:
+    if any(s.deaths[ally] < s.traversals for s in summaries.values()):
+      rows.append([f"{ally.name} deaths"]
+                  + [f"{s.deaths[ally] / s.traversals:.1%}" if s.traversals
+                     else "n/a" for s in summaries.values()])
+  rows.append(["Best", *(brief(s.best) for s in summaries.values())])
+  rows.append(["Worst", *(brief(s.worst) for s in summaries.values())])
+  widths = [max(map(len, column)) for column in zip(*rows)]
+  return "\n".join("  ".join(cell.ljust(width)
+                             for cell, width in zip(row, widths)).rstrip()
+                   for row in rows)
+
+class Me3Fate(enum.Enum):
+  """What becomes of an ally in Mass Effect 3, as described in the Scope section
+  of the README."""
+  # Dead or never recruited
+  ABSENT = "does not appear"
+  # Survived but was not loyal
+  DIES = "appears but dies"
+  # Survived and was loyal
+  WAR_ASSET = "may become a war asset"
+
+def me3_fates(outcome: DecodedOutcome) -> dict[Ally, Me3Fate]:
+  """Maps every ally to their fate in Mass Effect 3 given the outcome."""
+  fates: dict[Ally, Me3Fate] = {}
+  for ally in EVERYONE:
+    if not ally & outcome.spared:
+      fates[ally] = Me3Fate.ABSENT
+    elif ally & outcome.loyalty:
+      fates[ally] = Me3Fate.WAR_ASSET
+    else:
+      fates[ally] = Me3Fate.DIES
+  return fates
+
+def describe_me3(outcome: DecodedOutcome) -> str:
+  """Produces a human-readable string describing the Mass Effect 3 fate of each
+  ally given the outcome."""
+  return "\n".join(f"{ally.name}: {fate.value}"
+                   for ally, fate in me3_fates(outcome).items())
+
+def _ally_status(ally: Ally, outcome: DecodedOutcome, dead: Ally) -> str:
+  if ally & outcome.loyalty:
+    return "loyal"
+  if ally & outcome.spared:
+    return "disloyal"
+  return "dead" if ally & dead else ""
+
+def write_csv(outcomes: Iterable[OutcomeItem], file: TextIO, *,
+              delimiter: str = ",", per_ally: bool = True):
+  """Writes one CSV row per outcome item to file after a header row.
+
+  If per_ally is True, each ally has a column with their status in the encoded
+  traversal: loyal, disloyal, dead, or empty if they did not take part in the
+  final mission. Otherwise, the spared, loyal, and dead allies are listed by
+  name in one column each. The remaining columns are whether the crew and
+  Shepard survived and the traversal count.
+  """
+  writer = csv.writer(file, delimiter=delimiter)
+  if per_ally:
+    writer.writerow([ally.name for ally in EVERYONE]
+                    + ["Crew", "Shepard", "Traversals"])
+  else:
+    writer.writerow(["Spared", "Loyal", "Dead", "Crew", "Shepard",
+                     "Traversals"])
+  for item in outcomes:
+    outcome = decode_outcome(item[0])
+    dead = deaths(item)
+    if per_ally:
+      row = [_ally_status(ally, outcome, dead) for ally in EVERYONE]
+    else:
+      row = [str(outcome.spared), str(outcome.loyalty), str(dead)]
+    writer.writerow(row + [outcome.crew, outcome.shepard, item[1][0]])
+
+def write_sqlite(outcomes: Iterable[OutcomeItem], path: str, *,
+                 witnesses: bool = False):
+  """Writes the outcome items to a table named outcomes in the SQLite database
+  at path, replacing any existing table.
+
+  Besides the encoded outcome and the raw spared and loyalty bits, each row has
+  a crew and a Shepard survival column, a traversal count, and an indexed
+  column for each ally that is 1 if they were spared. If witnesses is True, the
+  encoded traversals and their descriptions are also written to a table named
+  witnesses. Encoded traversals are stored as text since they may not fit into
+  an SQLite integer.
+  """
+  names = [ally.name for ally in EVERYONE]
+  columns = ", ".join(f"{name} INTEGER NOT NULL" for name in names)
+  with sqlite3.connect(path) as connection:
+    connection.execute("DROP TABLE IF EXISTS outcomes")
+    connection.execute("DROP TABLE IF EXISTS witnesses")
+    connection.execute(
+      "CREATE TABLE outcomes (outcome INTEGER PRIMARY KEY, spared INTEGER, "
+      "loyalty INTEGER, crew INTEGER, shepard INTEGER, traversals INTEGER, "
+      f"{columns})")
+    for name in names:
+      connection.execute(f"CREATE INDEX outcomes_{name} ON outcomes ({name})")
+    if witnesses:
+      connection.execute(
+        "CREATE TABLE witnesses (outcome INTEGER PRIMARY KEY, traversal TEXT, "
+        "description TEXT)")
+    placeholders = ", ".join("?" * (len(names) + 6))
+    for outcome, (count, traversal) in outcomes:
+      decoded = decode_outcome(outcome)
+      connection.execute(
+        f"INSERT INTO outcomes VALUES ({placeholders})",
+        [outcome, decoded.spared.value, decoded.loyalty.value, decoded.crew,
+         decoded.shepard, count]
+        + [bool(ally & decoded.spared) for ally in EVERYONE])
+      if witnesses:
+        connection.execute("INSERT INTO witnesses VALUES (?, ?, ?)",
+                           (outcome, str(traversal),
+                            describe_traversal(traversal)))
+  connection.close()
\ No newline at end of file
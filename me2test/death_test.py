@@ -0,0 +1,200 @@
+#
+# Copyright (c) 2022 Andrew Lehmer
+#
+# Distributed under the MIT License.
+#
+
+from functools import reduce
+from operator import or_ as op_or
+from me2.ally import Ally, EVERYONE, REQUIRED
+from me2.bits import popcount
+from me2.death import *
+import json
+import unittest
+
+class ValidateTeamTest(unittest.TestCase):
+  def test_valid(self):
+    validate_team((Ally.Garrus | Ally.Samara).value)
+    validate_team((Ally.Garrus | Ally.Morinth).value)
+
+  def test_samara_and_morinth(self):
+    team = (Ally.Garrus | Ally.Samara | Ally.Morinth).value
+    with self.assertRaises(InvalidTeamError):
+      validate_team(team)
+    with self.assertRaises(InvalidTeamError):
+      get_victim(team, DP_NO_SHIELD_UPGRADE)
+    with self.assertRaises(InvalidTeamError):
+      get_defense_victims(team, 0)
+    # Excluding one of them makes the team valid again.
+    self.assertEqual(get_victim(team, DP_NO_WEAPON_UPGRADE, Ally.Samara.value),
+                     Ally.Garrus.value)
+
+  def test_skip_validation(self):
+    team = (Ally.Garrus | Ally.Samara | Ally.Morinth).value
+    self.assertEqual(get_victim(team, DP_NO_SHIELD_UPGRADE, validate=False),
+                     Ally.Garrus.value)
+    get_defense_victims(team, 0, validate=False)
+
+
+class GetVictimTest(unittest.TestCase):
+  def test_priority(self):
+    team = (Ally.Kasumi | Ally.Legion | Ally.Zaeed).value
+    self.assertEqual(get_victim(team, DP_NO_SHIELD_UPGRADE), Ally.Kasumi.value)
+
+  def test_exclude(self):
+    team = (Ally.Kasumi | Ally.Legion | Ally.Zaeed).value
+    self.assertEqual(
+      get_victim(team, DP_NO_SHIELD_UPGRADE, Ally.Kasumi.value),
+      Ally.Legion.value)
+
+  def test_no_victim(self):
+    with self.assertRaises(UnexpectedlyVictimlessError):
+      get_victim(Ally.Jack.value, DP_NO_SHIELD_UPGRADE)
+    with self.assertRaises(UnexpectedlyVictimlessError):
+      get_victim(Ally.Jack.value, DP_NO_ARMOR_UPGRADE, Ally.Jack.value)
+
+
+class GetVictimsTest(unittest.TestCase):
+  def test_sequence(self):
+    team = (Ally.Garrus | Ally.Jack | Ally.Samara).value
+    priorities = [DP_NO_ARMOR_UPGRADE, DP_NO_SHIELD_UPGRADE,
+                  DP_NO_WEAPON_UPGRADE]
+    self.assertEqual(get_victims(team, priorities),
+                     [Ally.Jack.value, Ally.Garrus.value, Ally.Samara.value])
+    # If Jack survives the armor check, she dies to the weapon check instead.
+    self.assertEqual(get_victims(team, priorities[1:]),
+                     [Ally.Garrus.value, Ally.Jack.value])
+
+  def test_skip(self):
+    team = (Ally.Garrus | Ally.Mordin).value
+    priorities = [DP_NO_ARMOR_UPGRADE, DP_NO_WEAPON_UPGRADE]
+    self.assertEqual(get_victims(team, priorities), [0, Ally.Garrus.value])
+
+
+class GetDefenseVictimsTest(unittest.TestCase):
+  def test_default_rules(self):
+    team = (Ally.Garrus | Ally.Mordin | Ally.Tali).value
+    self.assertEqual(get_defense_victims(team, 0), Ally.Mordin.value)
+
+  def test_custom_rules(self):
+    rules = DEFAULT_RULES._replace(defense=DEFAULT_RULES.defense[::-1])
+    team = (Ally.Garrus | Ally.Mordin | Ally.Tali).value
+    self.assertEqual(get_defense_victims(team, 0, rules), Ally.Garrus.value)
+
+  def test_exclude(self):
+    # Without Mordin, the remaining defenders hold the line.
+    team = (Ally.Garrus | Ally.Grunt | Ally.Jack | Ally.Mordin).value
+    self.assertEqual(get_defense_victims(team, 0), Ally.Mordin.value)
+    self.assertEqual(
+      get_defense_victims(team, 0, exclude=Ally.Mordin.value), 0)
+
+
+class ScoreTest(unittest.TestCase):
+  def test_loyalty(self):
+    self.assertEqual(score(Ally.Zaeed.value, True), 4)
+    self.assertEqual(score(Ally.Zaeed.value, False), 3)
+
+  def test_not_one_ally(self):
+    self.assertIsNone(score(0, True))
+    self.assertIsNone(score((Ally.Jack | Ally.Jacob).value, True))
+
+
+class TiersTest(unittest.TestCase):
+  def test_partition(self):
+    groups = [group for _, group in tiers()]
+    self.assertEqual(reduce(op_or, groups), EVERYONE.value)
+    self.assertEqual(sum(map(popcount, groups)), len(EVERYONE))
+
+  def test_order(self):
+    self.assertEqual([base for base, _ in tiers()], [4, 2, 1])
+    self.assertEqual(tiers()[0][1],
+                     (Ally.Garrus | Ally.Grunt | Ally.Zaeed).value)
+
+
+class SweepDefenseTest(unittest.TestCase):
+  def test_distribution(self):
+    # Only a fully loyal team avoids a death.
+    team = (Ally.Garrus | Ally.Mordin | Ally.Tali).value
+    self.assertEqual(sweep_defense(team),
+                     TollDistribution({0: 1, 1: 7}, 0, 1, 0.875))
+
+  def test_full_team(self):
+    team = EVERYONE & ~Ally.Morinth
+    distribution = sweep_defense(team.value)
+    self.assertEqual(sum(distribution.counts.values()), 1 << len(team))
+
+  def test_no_team(self):
+    with self.assertRaises(ValueError):
+      sweep_defense(0)
+
+
+class ExplainVictimTest(unittest.TestCase):
+  def test_shield(self):
+    team = Ally.Garrus | Ally.Kasumi | Ally.Legion | Ally.Tali | Ally.Jack
+    explanation = explain_victim(team.value, "no_shield_upgrade")
+    self.assertEqual(explanation.victim, Ally.Kasumi.value)
+    self.assertEqual(explanation.rank, 0)
+    self.assertEqual(explanation.ineligible, Ally.Jack.value)
+    self.assertEqual(str(explanation),
+      "Kasumi is highest in the shield-failure priority among your team "
+      "(ahead of Legion, Tali, and Garrus).")
+    self.assertEqual(explanation.ahead_of, (Ally.Legion.value, Ally.Tali.value,
+                                            Ally.Garrus.value))
+
+  def test_long_walk(self):
+    team = Ally.Jack | Ally.Miranda | Ally.Mordin
+    explanation = explain_victim(team.value, "the_long_walk")
+    self.assertEqual(explanation.rank, 1)
+    self.assertEqual(str(explanation),
+      "Jack is highest in the Long Walk priority among your team "
+      "(ahead of Mordin).")
+
+  def test_only_candidate(self):
+    team = Ally.Jack | Ally.Miranda
+    self.assertEqual(str(explain_victim(team.value, "no_armor_upgrade")),
+      "Jack is the only member of your team in the armor-failure priority.")
+
+  def test_no_candidate(self):
+    explanation = explain_victim(Ally.Miranda.value, "no_armor_upgrade")
+    self.assertEqual(explanation.victim, 0)
+    self.assertEqual(str(explanation),
+      "Nobody on your team is in the armor-failure priority.")
+
+  def test_defense(self):
+    with self.assertRaises(ValueError):
+      explain_victim(Ally.Mordin.value, "defense")
+
+
+class DiagnoseTest(unittest.TestCase):
+  def setUp(self) -> None:
+    self.team = (REQUIRED | Ally.Grunt | Ally.Tali | Ally.Zaeed).value
+
+  def test_one_cause(self):
+    self.assertEqual(diagnose(Ally.Tali.value, self.team),
+                     ["no_shield_upgrade"])
+
+  def test_several_causes(self):
+    self.assertEqual(diagnose(Ally.Jack.value, self.team),
+                     ["no_armor_upgrade", "the_long_walk"])
+
+  def test_no_cause(self):
+    self.assertEqual(diagnose(Ally.Miranda.value, self.team), [])
+
+
+class ExportTablesTest(unittest.TestCase):
+  def test_round_trip(self):
+    tables = json.loads(json.dumps(export_tables()))
+    self.assertEqual(tables["version"], TABLES_VERSION)
+    names = [priority["name"] for priority in tables["priorities"]]
+    self.assertEqual(names, list(PRIORITY_NAMES))
+    for priority in tables["priorities"]:
+      self.assertEqual([Ally[name].value for name in priority["allies"]],
+                       getattr(DEFAULT_RULES, priority["name"]))
+    self.assertEqual(
+      {Ally[name].value: score
+       for name, score in tables["defense_scores"].items()},
+      DEFAULT_RULES.defense_score)
+
+
+if __name__ == "__main__":
+  unittest.main()
\ No newline at end of file
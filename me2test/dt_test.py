@@ -0,0 +1,607 @@
+#
+# Copyright (c) 2022 Andrew Lehmer
+#
+# Distributed under the MIT License.
+#
+
+from math import comb
+from me2 import bits, death
+from me2.ally import *
+from me2.dt import Constraints, DecisionTree
+from me2.dt import decode_traversal, diff_outcomes, diff_traversals
+from me2.dt import evaluate_traversal, reconcile, validate_traversal
+from me2.encdec import Decoder, Encoder, decode_outcome, encode_outcome
+from me2.query import by_decision, describe_comparison, merge
+import os
+import random
+import tempfile
+from typing import Optional
+import unittest
+
+def generate_subtree(recruits: Ally,
+                     loyal: Ally,
+                     constraints: Constraints = Constraints()) -> DecisionTree:
+  """Generates the decision tree for a fixed recruitment and loyalty.
+
+  The loyalty of allies who are not recruited is ignored.
+  """
+  loyal &= recruits | REQUIRED
+  constraints = constraints._replace(recruits=recruits.value,
+                                     loyalty=loyal.value)
+  with tempfile.TemporaryDirectory() as directory:
+    dt = DecisionTree(os.path.join(directory, "me2.dat"),
+                      constraints=constraints)
+    dt.generate()
+  return dt
+
+def encode_traversal(tech: Ally, escort: Ally) -> int:
+  """Encodes a fully upgraded traversal with an unimportant second fireteam
+  leader and no meaningful biotic shield squad."""
+  encoder = Encoder()
+  encoder.encode_ally_optional((Ally.Grunt | Ally.Legion | Ally.Tali).value)
+  encoder.encode_ally_loyalty(Ally.Grunt.value)
+  for _ in range(3):
+    encoder.encode_bool(True)
+  encoder.encode_ally_value_as_index(tech.value)
+  encoder.encode_ally_value_as_index(Ally.Jack.value)
+  encoder.encode_ally_value_as_index(Ally.Garrus.value)
+  encoder.encode_ally_value_as_index(escort.value)
+  encoder.encode_bool(False)
+  encoder.encode_squad((Ally.Grunt | Ally.Miranda).value)
+  return encoder.result
+
+def encode_full_team_traversal(loyalty: Ally,
+                               armor: bool = True,
+                               escort: Ally = Ally.Zaeed,
+                               cargo_bay_picks: Optional[list[Ally]] = None
+                               ) -> int:
+  """Encodes a traversal with every ally recruited, the weapon upgrade, and
+  ideal specialists and leaders.
+
+  The shields are upgraded unless cargo_bay_picks are given. Garrus, Legion,
+  and Samara must be loyal.
+  """
+  encoder = Encoder()
+  encoder.encode_ally_optional(RECRUITABLE.value)
+  encoder.encode_ally_loyalty(loyalty.value)
+  encoder.encode_bool(armor)
+  encoder.encode_bool(cargo_bay_picks is None)
+  encoder.encode_bool(True)
+  if cargo_bay_picks is not None:
+    encoder.encode_choices([ally.value for ally in cargo_bay_picks])
+  encoder.encode_ally_value_as_index(Ally.Legion.value)
+  encoder.encode_bool(True)
+  encoder.encode_ideal_leaders((loyalty & IDEAL_LEADERS).value)
+  encoder.encode_ally_value_as_index(Ally.Samara.value)
+  encoder.encode_ally_value_as_index(Ally.Miranda.value)
+  encoder.encode_ally_value_as_index(escort.value)
+  encoder.encode_bool(False)
+  encoder.encode_squad((Ally.Mordin | Ally.Tali).value)
+  return encoder.result
+
+def encode_walk_traversal(unpicks: list[Ally]) -> int:
+  """Encodes a fully upgraded traversal with every ally recruited and loyal,
+  Jacob as the biotic specialist, and the given allies left out of the squad
+  in the biotic shield."""
+  loyal = EVERYONE & ~Ally.Morinth
+  encoder = Encoder()
+  encoder.encode_ally_optional(RECRUITABLE.value)
+  encoder.encode_ally_loyalty(loyal.value)
+  for _ in range(3):
+    encoder.encode_bool(True)
+  encoder.encode_ally_value_as_index(Ally.Legion.value)
+  encoder.encode_bool(True)
+  encoder.encode_ideal_leaders((loyal & IDEAL_LEADERS).value)
+  encoder.encode_ally_value_as_index(Ally.Jacob.value)
+  encoder.encode_ally_value_as_index(Ally.Miranda.value)
+  encoder.encode_ally_value_as_index(Ally.Zaeed.value)
+  encoder.encode_bool(True)
+  encoder.encode_choices([ally.value for ally in unpicks])
+  encoder.encode_squad((Ally.Mordin | Ally.Tali).value)
+  return encoder.result
+
+class RecruitmentRecorder(DecisionTree):
+  """Records the optional recruits of each recruitment branch without
+  descending into it."""
+  def _choose_loyalty_missions(self, team: int):
+    self.recruitments.append(team & ~REQUIRED.value)
+
+def record_recruitments(constraints: Constraints) -> list[int]:
+  with tempfile.TemporaryDirectory() as directory:
+    dt = RecruitmentRecorder(os.path.join(directory, "me2.dat"),
+                             constraints=constraints)
+    dt.recruitments = []
+    dt.generate()
+  return dt.recruitments
+
+def count_traversals(dt: DecisionTree) -> int:
+  """Counts the traversals recorded in the decision tree."""
+  return sum(count for count, _ in dt.outcomes.values())
+
+class MorinthTest(unittest.TestCase):
+  # The upgrades do not affect Morinth, so skip them to save time.
+  upgraded = Constraints(armor=True, shield=True, weapon=True)
+
+  def test_replaces_samara(self):
+    recruits = Ally.Grunt | Ally.Kasumi | Ally.Samara
+    dt = generate_subtree(recruits, EVERYONE, self.upgraded)
+    samara_and_morinth = Ally.Samara | Ally.Morinth
+    recruited_morinth = False
+    for outcome, (_, traversal) in dt.outcomes.items():
+      spared = decode_outcome(outcome).spared
+      self.assertNotEqual(spared & samara_and_morinth, samara_and_morinth)
+      if Decoder(traversal).decode_ally_optional() & Ally.Morinth:
+        recruited_morinth = True
+    self.assertTrue(recruited_morinth)
+
+  def test_requires_loyal_samara(self):
+    recruits = Ally.Grunt | Ally.Kasumi | Ally.Samara
+    dt = generate_subtree(recruits, EVERYONE & ~Ally.Samara, self.upgraded)
+    for outcome, (_, traversal) in dt.outcomes.items():
+      self.assertFalse(decode_outcome(outcome).spared & Ally.Morinth)
+      self.assertFalse(Decoder(traversal).decode_ally_optional() & Ally.Morinth)
+
+
+class SparedConstraintTest(unittest.TestCase):
+  def test_pruning(self):
+    recruits = Ally.Grunt | Ally.Kasumi | Ally.Legion
+    loyal = EVERYONE & ~Ally.Jack
+    spared = Ally.Kasumi | Ally.Mordin
+    full = generate_subtree(recruits, loyal)
+    pruned = generate_subtree(recruits, loyal, Constraints(spared.value))
+    expected = {outcome: value for outcome, value in full.outcomes.items()
+                if decode_outcome(outcome).spared & spared == spared}
+    self.assertTrue(expected)
+    self.assertEqual(pruned.outcomes, expected)
+    self.assertLess(pruned.visited, full.visited)
+
+
+class PinnedDecisionTest(unittest.TestCase):
+  recruits = Ally.Grunt | Ally.Legion | Ally.Samara
+  loyal = EVERYONE & ~(Ally.Garrus | Ally.Legion)
+
+  @classmethod
+  def setUpClass(cls):
+    cls.full = generate_subtree(cls.recruits, cls.loyal)
+
+  def test_booleans(self):
+    for decision in ("morinth", "armor", "shield", "weapon"):
+      with self.subTest(decision=decision):
+        pinned = [
+          generate_subtree(self.recruits, self.loyal,
+                           Constraints(**{decision: value}))
+          for value in (False, True)
+        ]
+        self.assertTrue(all(dt.outcomes for dt in pinned))
+        self.assertEqual(sum(map(count_traversals, pinned)),
+                         count_traversals(self.full))
+
+  def test_merge(self):
+    # Samara is tried before Morinth, so her encoded traversals come first.
+    shards = [
+      generate_subtree(self.recruits, self.loyal, Constraints(morinth=value))
+      for value in (False, True)
+    ]
+    self.assertEqual(merge(*(dt.outcomes for dt in shards)), self.full.outcomes)
+
+  def test_by_decision(self):
+    summaries = by_decision({
+      value: generate_subtree(self.recruits, self.loyal,
+                              Constraints(weapon=value)).outcomes
+      for value in (False, True)
+    })
+    without, upgraded = summaries[False], summaries[True]
+    self.assertEqual(without.traversals + upgraded.traversals,
+                     count_traversals(self.full))
+    # Without Thane, Garrus is the first victim of the weapon check.
+    self.assertLess(upgraded.deaths[Ally.Garrus] / upgraded.traversals,
+                    without.deaths[Ally.Garrus] / without.traversals)
+    self.assertEqual(without.deaths[Ally.Thane], without.traversals)
+    lines = describe_comparison(summaries).splitlines()
+    self.assertEqual(lines[0].split(), ["False", "True"])
+    self.assertEqual(lines[1].split(),
+                     ["Traversals", str(without.traversals),
+                      str(upgraded.traversals)])
+    self.assertFalse(any(line.startswith("Thane") for line in lines))
+
+  def test_no_escort(self):
+    dt = generate_subtree(self.recruits, self.loyal,
+                          Constraints(escort=NOBODY.value))
+    self.assertTrue(dt.outcomes)
+    self.assertFalse(any(decode_outcome(o).crew for o in dt.outcomes))
+
+  def test_escort(self):
+    dt = generate_subtree(self.recruits, self.loyal,
+                          Constraints(escort=Ally.Grunt.value))
+    self.assertTrue(dt.outcomes)
+    self.assertTrue(all(decode_outcome(o).crew for o in dt.outcomes))
+    self.assertLess(count_traversals(dt), count_traversals(self.full))
+
+  def test_loyalty_without_recruits(self):
+    # Recruitments without Samara cannot satisfy her pinned loyalty mission.
+    loyal = Ally.Garrus | Ally.Samara
+    constraints = Constraints(loyalty=loyal.value)
+    recruitments = record_recruitments(constraints)
+    self.assertEqual(len(recruitments),
+                     sum(comb(len(RECRUITABLE) - 1, n - 1)
+                         for n in range(3, len(RECRUITABLE) + 1)))
+    self.assertTrue(all(r & Ally.Samara.value for r in recruitments))
+    with tempfile.TemporaryDirectory() as directory:
+      dt = DecisionTree(os.path.join(directory, "me2.dat"),
+                        constraints=constraints)
+      self.assertFalse(dt.generate(max_visits=2000))
+    self.assertTrue(dt.outcomes)
+    for _, traversal in dt.outcomes.values():
+      decoded = decode_traversal(traversal)
+      self.assertTrue(decoded.recruits & Ally.Samara)
+      self.assertEqual(decoded.loyalty & LOYALTY_MASK, loyal)
+
+
+class OutcomeDiffTest(unittest.TestCase):
+  def test_identical(self):
+    outcome = encode_outcome((Ally.Garrus | Ally.Tali).value, 0, True)
+    self.assertEqual(diff_outcomes(outcome, outcome), [])
+
+  def test_survival_and_loyalty(self):
+    a = encode_outcome((Ally.Garrus | Ally.Tali | Ally.Thane).value,
+                       Ally.Garrus.value, True)
+    b = encode_outcome((Ally.Garrus | Ally.Tali | Ally.Zaeed).value,
+                       (Ally.Tali | Ally.Zaeed).value, False)
+    self.assertEqual(diff_outcomes(a, b), [
+      "Newly spared: Zaeed",
+      "Newly dead: Thane",
+      "Newly loyal: Tali",
+      "No longer loyal: Garrus",
+      "Crew: survived -> dead"
+    ])
+
+  def test_shepard(self):
+    a = encode_outcome((Ally.Garrus | Ally.Tali).value, 0, False)
+    b = encode_outcome(Ally.Tali.value, 0, False)
+    self.assertEqual(diff_outcomes(a, b),
+                     ["Newly dead: Garrus", "Shepard: survived -> dead"])
+
+
+class EvaluateTraversalTest(unittest.TestCase):
+  full_team = EVERYONE & ~Ally.Morinth
+
+  def test_everyone_survives(self):
+    traversal = encode_full_team_traversal(self.full_team)
+    self.assertEqual(evaluate_traversal(traversal),
+                     encode_outcome(self.full_team.value,
+                                    self.full_team.value, True))
+
+  def test_no_armor_upgrade(self):
+    traversal = encode_full_team_traversal(self.full_team, armor=False)
+    spared = self.full_team & ~Ally.Jack
+    self.assertEqual(evaluate_traversal(traversal),
+                     encode_outcome(spared.value, spared.value, True))
+
+  def test_disloyal_escort_and_squadmate(self):
+    loyalty = self.full_team & ~(Ally.Tali | Ally.Zaeed)
+    traversal = encode_full_team_traversal(loyalty)
+    self.assertEqual(evaluate_traversal(traversal),
+                     encode_outcome(loyalty.value, loyalty.value, True))
+
+  def test_no_escort(self):
+    traversal = encode_full_team_traversal(self.full_team, escort=NOBODY)
+    self.assertEqual(evaluate_traversal(traversal),
+                     encode_outcome(self.full_team.value,
+                                    self.full_team.value, False))
+
+  def test_recorded_outcomes(self):
+    dt = DecisionTree(os.path.join(os.path.dirname(__file__), "..", "me2.dat"))
+    items = random.Random(2022).sample(list(dt.outcomes.items()), 2000)
+    for outcome, (_, traversal) in items:
+      self.assertEqual(evaluate_traversal(traversal), outcome)
+
+  def test_invalid(self):
+    # Kasumi was not recruited.
+    with self.assertRaises(ValueError):
+      evaluate_traversal(encode_traversal(Ally.Kasumi, NOBODY))
+
+  def test_cargo_bay_squad(self):
+    # Leaving Kasumi behind makes her the victim of the shields.
+    spared = self.full_team & ~Ally.Kasumi
+    traversal = encode_full_team_traversal(self.full_team,
+                                           cargo_bay_picks=[Ally.Kasumi])
+    self.assertEqual(evaluate_traversal(traversal),
+                     encode_outcome(spared.value, spared.value, True))
+    # Kasumi is ahead of Grunt in the priority, so leaving him behind instead
+    # cannot kill him.
+    forged = encode_full_team_traversal(self.full_team,
+                                        cargo_bay_picks=[Ally.Grunt])
+    with self.assertRaises(ValueError):
+      evaluate_traversal(forged)
+
+  def test_walk_squad(self):
+    spared = self.full_team & ~Ally.Jack
+    self.assertEqual(
+      evaluate_traversal(encode_walk_traversal([Ally.Thane, Ally.Jack])),
+      encode_outcome(spared.value, spared.value, True))
+    with self.assertRaises(ValueError):
+      evaluate_traversal(encode_walk_traversal([Ally.Thane, Ally.Kasumi]))
+
+  def test_first_leader_without_ideal_leaders(self):
+    # Without a loyal, ideal leader, nobody can protect Legion.
+    loyalty = self.full_team & ~(Ally.Garrus | Ally.Jacob | Ally.Miranda)
+    with self.assertRaises(ValueError):
+      evaluate_traversal(encode_full_team_traversal(loyalty))
+
+
+class ReconcileTest(unittest.TestCase):
+  full_team = EVERYONE & ~Ally.Morinth
+
+  def setUp(self) -> None:
+    self.traversal = encode_full_team_traversal(self.full_team)
+
+  def test_consistent(self):
+    reconciliation = reconcile(self.traversal,
+                               evaluate_traversal(self.traversal))
+    self.assertEqual(reconciliation.differences, [])
+    self.assertEqual(reconciliation.suggestions, [])
+    self.assertEqual(str(reconciliation),
+                     "The observed outcome matches the prediction.")
+
+  def test_no_shield_upgrade(self):
+    spared = self.full_team & ~Ally.Kasumi
+    observed = encode_outcome(spared.value, spared.value, True)
+    reconciliation = reconcile(self.traversal, observed)
+    self.assertEqual(reconciliation.differences, ["Newly dead: Kasumi"])
+    self.assertIn("Cyclonic Shields (Kasumi)", reconciliation.suggestions)
+    self.assertEqual(str(reconciliation),
+      "Predicted: 12 allies and the crew survived.\n"
+      "Observed:  11 allies and the crew survived.\n"
+      "Newly dead: Kasumi\n"
+      "Decisions that could explain the differences:\n"
+      "  Loyalty missions (Kasumi)\n"
+      "  Cyclonic Shields (Kasumi)")
+
+  def test_crew(self):
+    traversal = encode_full_team_traversal(self.full_team, escort=NOBODY)
+    observed = encode_outcome(self.full_team.value, self.full_team.value, True)
+    reconciliation = reconcile(traversal, observed)
+    self.assertEqual(reconciliation.differences, ["Crew: dead -> survived"])
+    self.assertEqual(reconciliation.suggestions, ["Crew escort (the crew)"])
+
+
+class RecruitCountsTest(unittest.TestCase):
+  def test_default(self):
+    self.assertEqual(len(record_recruitments(Constraints())),
+                     sum(comb(len(RECRUITABLE), k)
+                         for k in range(3, len(RECRUITABLE) + 1)))
+
+  def test_minimal(self):
+    recruitments = record_recruitments(Constraints(recruit_counts=range(3, 4)))
+    self.assertEqual(len(set(recruitments)), comb(len(RECRUITABLE), 3))
+    self.assertTrue(all(bits.popcount(r) == 3 for r in recruitments))
+
+  def test_minimal_with_morinth(self):
+    # Sparing Morinth requires recruiting Samara.
+    recruitments = record_recruitments(
+      Constraints(spared=Ally.Morinth.value, recruit_counts=range(3, 4)))
+    self.assertEqual(len(recruitments), comb(len(RECRUITABLE) - 1, 2))
+    self.assertTrue(all(r & Ally.Samara.value for r in recruitments))
+
+
+class BudgetTest(unittest.TestCase):
+  recruits = Ally.Grunt | Ally.Legion | Ally.Samara
+  loyal = EVERYONE & ~(Ally.Garrus | Ally.Legion)
+
+  @classmethod
+  def setUpClass(cls):
+    loyal = cls.loyal & (cls.recruits | REQUIRED)
+    cls.constraints = Constraints(recruits=cls.recruits.value,
+                                  loyalty=loyal.value)
+    cls.full = generate_subtree(cls.recruits, cls.loyal)
+
+  def test_resume(self):
+    with tempfile.TemporaryDirectory() as directory:
+      dt = DecisionTree(os.path.join(directory, "me2.dat"),
+                        constraints=self.constraints)
+      pauses = 0
+      while not dt.generate(max_visits=1000):
+        pauses += 1
+    self.assertGreater(pauses, 1)
+    self.assertEqual(dt.outcomes, self.full.outcomes)
+
+  def test_resume_from_file(self):
+    with tempfile.TemporaryDirectory() as directory:
+      file_path = os.path.join(directory, "me2.dat")
+      dt = DecisionTree(file_path, constraints=self.constraints)
+      self.assertFalse(dt.generate(max_visits=self.full.visited // 2))
+      self.assertNotEqual(dt.outcomes, self.full.outcomes)
+      dt = DecisionTree(file_path, constraints=self.constraints)
+      self.assertTrue(dt.generate())
+    self.assertEqual(dt.outcomes, self.full.outcomes)
+
+  def test_resume_with_different_options(self):
+    with tempfile.TemporaryDirectory() as directory:
+      file_path = os.path.join(directory, "me2.dat")
+      dt = DecisionTree(file_path, constraints=self.constraints)
+      self.assertFalse(dt.generate(max_visits=self.full.visited // 2))
+      with self.assertRaisesRegex(ValueError, "different constraints"):
+        DecisionTree(file_path, constraints=self.constraints._replace(
+          spared=Ally.Grunt.value))
+      rules = death.DEFAULT_RULES._replace(
+        the_long_walk=death.DP_THE_LONG_WALK[::-1])
+      with self.assertRaisesRegex(ValueError, "different rules"):
+        DecisionTree(file_path, rules, self.constraints)
+
+  def test_pause_before_start(self):
+    with tempfile.TemporaryDirectory() as directory:
+      dt = DecisionTree(os.path.join(directory, "me2.dat"),
+                        constraints=self.constraints)
+      dt.pause()
+      self.assertFalse(dt.generate())
+      self.assertFalse(dt.outcomes)
+      self.assertTrue(dt.generate())
+    self.assertEqual(dt.outcomes, self.full.outcomes)
+
+  def test_timeout(self):
+    with tempfile.TemporaryDirectory() as directory:
+      dt = DecisionTree(os.path.join(directory, "me2.dat"))
+      self.assertFalse(dt.generate(timeout=0.1))
+      self.assertFalse(dt.is_complete())
+
+  def test_too_few_visits(self):
+    with tempfile.TemporaryDirectory() as directory:
+      dt = DecisionTree(os.path.join(directory, "me2.dat"))
+      with self.assertRaises(ValueError):
+        dt.generate(max_visits=1)
+
+
+class ConstraintsValidationTest(unittest.TestCase):
+  def test_valid(self):
+    Constraints(recruits=(Ally.Grunt | Ally.Legion | Ally.Samara).value,
+                loyalty=Ally.Samara.value, morinth=True,
+                escort=Ally.Morinth.value).validate()
+
+  def test_too_few_recruits(self):
+    with self.assertRaises(ValueError):
+      Constraints(recruits=(Ally.Grunt | Ally.Legion).value).validate()
+
+  def test_recruit_counts_out_of_bounds(self):
+    for counts in (range(2, 4), range(7, 9), range(5, 5)):
+      with self.assertRaises(ValueError):
+        Constraints(recruit_counts=counts).validate()
+
+  def test_recruits_outside_recruit_counts(self):
+    constraints = Constraints(
+      recruits=(Ally.Grunt | Ally.Legion | Ally.Tali).value,
+      recruit_counts=range(4, 8))
+    with self.assertRaises(ValueError):
+      constraints.validate()
+
+  def test_too_many_spared_for_recruit_counts(self):
+    # Sparing Morinth requires recruiting Samara, too.
+    constraints = Constraints(
+      spared=(Ally.Grunt | Ally.Legion | Ally.Tali | Ally.Morinth).value,
+      recruit_counts=range(3, 4))
+    with self.assertRaises(ValueError):
+      constraints.validate()
+
+  def test_loyal_not_recruited(self):
+    constraints = Constraints(
+      recruits=(Ally.Grunt | Ally.Legion | Ally.Tali).value,
+      loyalty=Ally.Zaeed.value)
+    with self.assertRaises(ValueError):
+      constraints.validate()
+
+  def test_morinth_without_samara(self):
+    constraints = Constraints(
+      recruits=(Ally.Grunt | Ally.Legion | Ally.Samara).value,
+      loyalty=Ally.Grunt.value, morinth=True)
+    with self.assertRaises(ValueError):
+      constraints.validate()
+
+  def test_escort_not_recruited(self):
+    constraints = Constraints(
+      recruits=(Ally.Grunt | Ally.Legion | Ally.Tali).value,
+      escort=Ally.Zaeed.value)
+    with self.assertRaises(ValueError):
+      constraints.validate()
+
+  def test_spared_not_recruited(self):
+    constraints = Constraints(
+      spared=Ally.Zaeed.value,
+      recruits=(Ally.Grunt | Ally.Legion | Ally.Tali).value)
+    with self.assertRaises(ValueError):
+      constraints.validate()
+
+  def test_spared_samara_and_morinth(self):
+    with self.assertRaises(ValueError):
+      Constraints(spared=(Ally.Samara | Ally.Morinth).value).validate()
+
+  def test_spared_without_armor(self):
+    with self.assertRaises(ValueError):
+      Constraints(spared=Ally.Jack.value, armor=False).validate()
+    Constraints(spared=Ally.Jack.value, armor=True).validate()
+
+  def test_spared_disloyal_escort(self):
+    constraints = Constraints(
+      spared=Ally.Grunt.value,
+      recruits=(Ally.Grunt | Ally.Legion | Ally.Tali).value,
+      loyalty=Ally.Legion.value,
+      escort=Ally.Grunt.value)
+    with self.assertRaises(ValueError):
+      constraints.validate()
+    constraints._replace(loyalty=Ally.Grunt.value).validate()
+
+  def test_miranda_escort(self):
+    with self.assertRaises(ValueError):
+      DecisionTree("nonexistent.dat", constraints=Constraints(
+        escort=Ally.Miranda.value))
+
+
+class TraversalDiffTest(unittest.TestCase):
+  def test_decode(self):
+    decoded = decode_traversal(encode_traversal(Ally.Legion, NOBODY))
+    self.assertEqual(decoded.tech, Ally.Legion)
+    self.assertIsNone(decoded.cargo_bay_squad)
+    self.assertIsNone(decoded.first_leader)
+    self.assertIsNone(decoded.walk_squad)
+    self.assertEqual(decoded.final_squad, Ally.Grunt | Ally.Miranda)
+
+  def test_identical(self):
+    traversal = encode_traversal(Ally.Legion, NOBODY)
+    self.assertEqual(diff_traversals(traversal, traversal), [])
+
+  def test_two_decisions(self):
+    self.assertEqual(
+      diff_traversals(encode_traversal(Ally.Legion, NOBODY),
+                      encode_traversal(Ally.Tali, Ally.Grunt)),
+      ["Tech specialist: Legion -> Tali", "Crew escort: nobody -> Grunt"])
+
+
+class ValidateTraversalTest(unittest.TestCase):
+  def test_generated(self):
+    dt = generate_subtree(Ally.Grunt | Ally.Legion | Ally.Samara,
+                          EVERYONE & ~(Ally.Garrus | Ally.Legion))
+    for _, traversal in dt.outcomes.values():
+      self.assertEqual(validate_traversal(traversal), [])
+
+  def test_walk_squad(self):
+    # Thane is left behind, so Jack is the next victim in the biotic shield.
+    self.assertEqual(validate_traversal(
+      encode_walk_traversal([Ally.Thane, Ally.Jack])), [])
+    self.assertEqual(validate_traversal(
+      encode_walk_traversal([Ally.Thane, Ally.Kasumi])), [
+      "Biotic shield squad: Kasumi is not the next victim after those left "
+      "behind"
+    ])
+
+  def test_first_leader_without_ideal_leaders(self):
+    loyalty = EVERYONE & ~(Ally.Garrus | Ally.Jacob | Ally.Miranda
+                           | Ally.Morinth)
+    self.assertEqual(validate_traversal(encode_full_team_traversal(loyalty)), [
+      "Second fireteam leader: No ideal leader remains to protect the tech"
+    ])
+
+  def test_violations(self):
+    encoder = Encoder()
+    encoder.encode_ally_optional((Ally.Grunt | Ally.Morinth).value)
+    encoder.encode_ally_loyalty(Ally.Tali.value)
+    for _ in range(3):
+      encoder.encode_bool(True)
+    encoder.encode_ally_value_as_index(Ally.Kasumi.value)
+    encoder.encode_ally_value_as_index(Ally.Garrus.value)
+    encoder.encode_ally_value_as_index(Ally.Garrus.value)
+    encoder.encode_ally_value_as_index(Ally.Kasumi.value)
+    encoder.encode_bool(False)
+    encoder.encode_squad((Ally.Thane | Ally.Zaeed).value)
+    self.assertEqual(validate_traversal(encoder.result), [
+      "Recruits: Morinth requires Samara's loyalty mission",
+      "Recruits: At least three optional allies are required, but only Grunt "
+      "and Morinth remain",
+      "Loyalty missions: Tali not recruited",
+      "Tech specialist: Kasumi cannot be the tech specialist",
+      "Biotic specialist: Garrus cannot be the biotic specialist",
+      "Diversion team leader: Garrus cannot lead the diversion team",
+      "Crew escort: Kasumi cannot escort the crew",
+      "Final squad: Thane and Zaeed not available"
+    ])
+
+
+if __name__ == "__main__":
+  unittest.main()
\ No newline at end of file
@@ -5,6 +5,7 @@
 #
 
 from me2.ally import Ally
+from me2.death import InvalidTeamError
 from me2.encdec import DecodedOutcome, Decoder, Encoder
 from me2.encdec import decode_outcome, encode_outcome
 import unittest
@@ -64,6 +65,18 @@ class OutcomeEncoderTest(unittest.TestCase):
     )
     self.assertEqual(encoded_outcome, 0x2044422)
 
+  def test_not_allies(self):
+    with self.assertRaises(ValueError):
+      encode_outcome(spared = 1 << len(Ally), loyalty = 0, crew = True)
+
+  def test_samara_and_morinth(self):
+    with self.assertRaises(InvalidTeamError):
+      encode_outcome(spared = (Ally.Samara | Ally.Morinth).value,
+                     loyalty = 0, crew = True)
+    # The generator skips the check for teams that are valid by construction.
+    encode_outcome(spared = (Ally.Samara | Ally.Morinth).value,
+                   loyalty = 0, crew = True, validate = False)
+
 
 class DecoderTest(unittest.TestCase):
   def test_decode_bool(self):
@@ -114,6 +127,31 @@ class OutcomeDecoderTest(unittest.TestCase):
       )
     )
 
+  def test_loyalty_of_the_dead(self):
+    decoded = decode_outcome(encode_outcome(
+      spared = (Ally.Grunt | Ally.Morinth).value,
+      loyalty = (Ally.Grunt | Ally.Thane).value,
+      crew = False
+    ))
+    self.assertEqual(decoded.loyalty, Ally.Grunt | Ally.Morinth)
+    self.assertEqual(decoded.loyalty & decoded.spared, decoded.loyalty)
+
+  def test_too_long(self):
+    with self.assertRaises(ValueError):
+      decode_outcome(0x20940cb | 1 << 26)
+    with self.assertRaises(ValueError):
+      decode_outcome(-1)
+
+  def test_samara_and_morinth(self):
+    samara_and_morinth = (Ally.Samara | Ally.Morinth).value
+    with self.assertRaises(InvalidTeamError):
+      decode_outcome(samara_and_morinth)
+
+  def test_shepard(self):
+    for spared, shepard in ((Ally.Tali, False), (Ally.Grunt | Ally.Tali, True)):
+      decoded = decode_outcome(encode_outcome(spared.value, 0, True))
+      self.assertEqual(decoded.shepard, shepard)
+
 
 if __name__ == "__main__":
   unittest.main()
\ No newline at end of file
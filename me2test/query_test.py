@@ -0,0 +1,340 @@
+#
+# Copyright (c) 2022 Andrew Lehmer
+#
+# Distributed under the MIT License.
+#
+
+from me2.ally import Ally, EVERYONE, NOBODY, REQUIRED
+from me2.dt import describe_traversal
+from me2.encdec import Encoder, decode_outcome, encode_outcome
+from fractions import Fraction
+from me2.query import *
+import io
+import os
+import random
+import sqlite3
+import tempfile
+import unittest
+
+# A small outcome dictionary in the same format as DecisionTree.outcomes
+_EVERYONE_LOYAL = encode_outcome(
+  spared = (Ally.Garrus | Ally.Tali | Ally.Zaeed).value,
+  loyalty = (Ally.Garrus | Ally.Tali | Ally.Zaeed).value,
+  crew = True)
+_ZAEED_DIES = encode_outcome(
+  spared = (Ally.Garrus | Ally.Tali).value,
+  loyalty = Ally.Garrus.value,
+  crew = True)
+_CREW_DIES = encode_outcome(
+  spared = (Ally.Garrus | Ally.Tali | Ally.Zaeed).value,
+  loyalty = Ally.Garrus.value,
+  crew = False)
+OUTCOMES = {
+  _EVERYONE_LOYAL: (5, 0x500),
+  _ZAEED_DIES: (2, 0x200),
+  _CREW_DIES: (3, 0x300)
+}
+
+def _encode_partial_roster() -> int:
+  """Encodes a traversal that recruits only Grunt, Legion, and Tali and
+  upgrades everything."""
+  encoder = Encoder()
+  encoder.encode_ally_optional((Ally.Grunt | Ally.Legion | Ally.Tali).value)
+  encoder.encode_ally_loyalty(Ally.Grunt.value)
+  for _ in range(3):
+    encoder.encode_bool(True)
+  for ally in (Ally.Legion, Ally.Jack, Ally.Garrus, NOBODY):
+    encoder.encode_ally_value_as_index(ally.value)
+  encoder.encode_bool(False)
+  encoder.encode_squad((Ally.Grunt | Ally.Miranda).value)
+  return encoder.result
+
+_PARTIAL_ROSTER = _encode_partial_roster()
+
+class FindTest(unittest.TestCase):
+  def test_found(self):
+    item = find(OUTCOMES.items(), lambda o: not o.spared & Ally.Zaeed)
+    self.assertEqual(item, (_ZAEED_DIES, (2, 0x200)))
+
+  def test_first(self):
+    item = find(OUTCOMES.items(), lambda o: bool(o.spared & Ally.Zaeed))
+    self.assertEqual(item, (_EVERYONE_LOYAL, (5, 0x500)))
+
+  def test_not_found(self):
+    self.assertIsNone(find(OUTCOMES.items(), lambda o: not o.spared))
+
+
+class FractionTest(unittest.TestCase):
+  def test_unconditional(self):
+    self.assertEqual(fraction(OUTCOMES.items(), lambda o: o.crew),
+                     Fraction(7, 10))
+
+  def test_conditional(self):
+    # Of the 8 traversals that spare Zaeed, 5 also make him loyal.
+    self.assertEqual(
+      fraction(OUTCOMES.items(), lambda o: bool(o.loyalty & Ally.Zaeed),
+               lambda o: bool(o.spared & Ally.Zaeed)),
+      Fraction(5, 8))
+
+  def test_several(self):
+    queries = [(lambda o: o.crew, lambda o: len(o.loyalty) == 1),
+               (lambda o: not o.crew, lambda _: True)]
+    self.assertEqual(fractions(OUTCOMES.items(), queries),
+                     [Fraction(2, 5), Fraction(3, 10)])
+
+  def test_impossible_condition(self):
+    with self.assertRaises(ZeroDivisionError):
+      fraction(OUTCOMES.items(), lambda o: o.crew, lambda o: not o.spared)
+
+
+class MergeTest(unittest.TestCase):
+  def test_disjoint(self):
+    first = {_EVERYONE_LOYAL: OUTCOMES[_EVERYONE_LOYAL]}
+    second = {_ZAEED_DIES: OUTCOMES[_ZAEED_DIES],
+              _CREW_DIES: OUTCOMES[_CREW_DIES]}
+    self.assertEqual(merge(first, second), OUTCOMES)
+
+  def test_overlapping(self):
+    merged = merge(OUTCOMES, {_ZAEED_DIES: (4, 0x201)})
+    self.assertEqual(merged[_ZAEED_DIES], (6, 0x201))
+    self.assertEqual(merged[_CREW_DIES], OUTCOMES[_CREW_DIES])
+
+  def test_nothing(self):
+    self.assertEqual(merge(), {})
+
+
+class RollupTest(unittest.TestCase):
+  def test_spared(self):
+    spared = (Ally.Garrus | Ally.Tali | Ally.Zaeed).value
+    self.assertEqual(rollup(OUTCOMES.items()), {
+      encode_outcome(spared, 0, False): (8, 0x300),
+      encode_outcome((Ally.Garrus | Ally.Tali).value, 0, False): (2, 0x200)
+    })
+
+  def test_keep_everything(self):
+    self.assertEqual(rollup(OUTCOMES.items(), loyalty=True, crew=True),
+                     OUTCOMES)
+
+  def test_composable(self):
+    rolled_up = rollup(OUTCOMES.items(), crew=True)
+    self.assertEqual(len(rolled_up), len(OUTCOMES))
+    self.assertEqual(rollup(rolled_up.items()), rollup(OUTCOMES.items()))
+
+  def test_counts_conserved(self):
+    for loyalty in (False, True):
+      for crew in (False, True):
+        rolled_up = rollup(OUTCOMES.items(), loyalty=loyalty, crew=crew)
+        self.assertEqual(sum(count for count, _ in rolled_up.values()),
+                         sum(count for count, _ in OUTCOMES.values()))
+
+
+class SampleTest(unittest.TestCase):
+  def test_seeded(self):
+    self.assertEqual(sample(OUTCOMES.items(), 10, random.Random(2022)),
+                     sample(OUTCOMES.items(), 10, random.Random(2022)))
+
+  def test_weighted(self):
+    draws = sample(OUTCOMES.items(), 100000, random.Random(2022))
+    total = sum(count for count, _ in OUTCOMES.values())
+    for outcome, (count, _) in OUTCOMES.items():
+      frequency = sum(1 for item in draws if item[0] == outcome) / len(draws)
+      self.assertAlmostEqual(frequency, count / total, delta=0.01)
+
+  def test_unreachable(self):
+    outcomes = {**OUTCOMES, _CREW_DIES: (0, 0x300)}
+    draws = sample(outcomes.items(), 1000, random.Random(2022))
+    self.assertNotIn(_CREW_DIES, (outcome for outcome, _ in draws))
+
+
+class RankTest(unittest.TestCase):
+  def test_order(self):
+    ranked = sorted(OUTCOMES, key=lambda o: rank(decode_outcome(o)))
+    self.assertEqual(ranked, [_ZAEED_DIES, _CREW_DIES, _EVERYONE_LOYAL])
+
+  def test_loyalty_breaks_ties(self):
+    disloyal = encode_outcome(
+      spared = (Ally.Garrus | Ally.Tali | Ally.Zaeed).value,
+      loyalty = Ally.Garrus.value,
+      crew = True)
+    self.assertLess(rank(decode_outcome(disloyal)),
+                    rank(decode_outcome(_EVERYONE_LOYAL)))
+    self.assertGreater(rank(decode_outcome(disloyal)),
+                       rank(decode_outcome(_CREW_DIES)))
+
+
+class BestTest(unittest.TestCase):
+  def test_best(self):
+    self.assertEqual(best(OUTCOMES.items()), (_EVERYONE_LOYAL, (5, 0x500)))
+
+  def test_tie(self):
+    # The spared allies differ, but the ranks are the same.
+    tied = encode_outcome(
+      spared = (Ally.Jack | Ally.Miranda | Ally.Thane).value,
+      loyalty = (Ally.Jack | Ally.Miranda | Ally.Thane).value,
+      crew = True)
+    outcomes = {tied: (1, 0x100), **OUTCOMES}
+    self.assertEqual(best(outcomes.items()), (tied, (1, 0x100)))
+
+  def test_empty(self):
+    self.assertIsNone(best({}.items()))
+
+
+class WorstTest(unittest.TestCase):
+  def test_worst(self):
+    self.assertEqual(worst(OUTCOMES.items()), (_ZAEED_DIES, (2, 0x200)))
+
+  def test_tie(self):
+    tied = encode_outcome(
+      spared = (Ally.Jack | Ally.Miranda).value,
+      loyalty = Ally.Miranda.value,
+      crew = True)
+    outcomes = {**OUTCOMES, tied: (1, 0x100)}
+    self.assertEqual(worst(outcomes.items()), (_ZAEED_DIES, (2, 0x200)))
+
+  def test_empty(self):
+    self.assertIsNone(worst({}.items()))
+
+
+class DominatesTest(unittest.TestCase):
+  def test_fixtures(self):
+    everyone_loyal = decode_outcome(_EVERYONE_LOYAL)
+    zaeed_dies = decode_outcome(_ZAEED_DIES)
+    crew_dies = decode_outcome(_CREW_DIES)
+    self.assertTrue(dominates(everyone_loyal, zaeed_dies))
+    self.assertTrue(dominates(everyone_loyal, crew_dies))
+    self.assertFalse(dominates(zaeed_dies, everyone_loyal))
+    # Neither outcome dominates the other.
+    self.assertFalse(dominates(zaeed_dies, crew_dies))
+    self.assertFalse(dominates(crew_dies, zaeed_dies))
+
+  def test_partial_order(self):
+    rng = random.Random(2022)
+    def random_outcome() -> DecodedOutcome:
+      # Stick to a few allies so that comparable outcomes are common.
+      spared = rng.getrandbits(4)
+      return decode_outcome(
+        encode_outcome(spared, rng.getrandbits(4), rng.random() < 0.5))
+    outcomes = [random_outcome() for _ in range(40)]
+    for a in outcomes:
+      self.assertTrue(dominates(a, a))
+      for b in outcomes:
+        if dominates(a, b) and dominates(b, a):
+          self.assertEqual((a.spared, a.crew), (b.spared, b.crew))
+        for c in outcomes:
+          if dominates(a, b) and dominates(b, c):
+            self.assertTrue(dominates(a, c))
+
+
+class DeathsTest(unittest.TestCase):
+  def setUp(self) -> None:
+    self.traversal = _PARTIAL_ROSTER
+
+  def test_partial_roster(self):
+    spared = (REQUIRED & ~Ally.Jack) | Ally.Grunt
+    outcome = encode_outcome(spared.value, Ally.Grunt.value, False)
+    self.assertEqual(deaths((outcome, (1, self.traversal))),
+                     Ally.Jack | Ally.Legion | Ally.Tali)
+
+  def test_no_deaths(self):
+    spared = REQUIRED | Ally.Grunt | Ally.Legion | Ally.Tali
+    outcome = encode_outcome(spared.value, Ally.Grunt.value, False)
+    self.assertFalse(deaths((outcome, (1, self.traversal))))
+
+  def test_perfect(self):
+    spared = REQUIRED | Ally.Grunt | Ally.Legion | Ally.Tali
+    for crew in (False, True):
+      outcome = decode_outcome(
+        encode_outcome(spared.value, Ally.Grunt.value, crew))
+      self.assertEqual(is_perfect(outcome, spared), crew)
+      # Recruiting Kasumi as well leads to the same outcome if she dies.
+      self.assertFalse(is_perfect(outcome, spared | Ally.Kasumi))
+
+
+class Me3FatesTest(unittest.TestCase):
+  def test_fates(self):
+    fates = me3_fates(decode_outcome(_ZAEED_DIES))
+    self.assertEqual(fates[Ally.Garrus], Me3Fate.WAR_ASSET)
+    self.assertEqual(fates[Ally.Tali], Me3Fate.DIES)
+    self.assertEqual(fates[Ally.Zaeed], Me3Fate.ABSENT)
+    self.assertEqual(fates[Ally.Mordin], Me3Fate.ABSENT)
+    self.assertEqual(len(fates), len(EVERYONE))
+
+  def test_describe(self):
+    lines = describe_me3(decode_outcome(_ZAEED_DIES)).splitlines()
+    self.assertIn("Garrus: may become a war asset", lines)
+    self.assertIn("Tali: appears but dies", lines)
+    self.assertIn("Zaeed: does not appear", lines)
+
+
+class WriteCsvTest(unittest.TestCase):
+  def setUp(self) -> None:
+    spared = REQUIRED & ~Ally.Jack | Ally.Grunt
+    self.outcomes = {
+      encode_outcome(spared.value, (Ally.Grunt | Ally.Jacob).value, True):
+        (4, _PARTIAL_ROSTER)
+    }
+
+  def test_per_ally(self):
+    output = io.StringIO(newline="")
+    write_csv(self.outcomes.items(), output)
+    self.assertEqual(output.getvalue(),
+      "Garrus,Jacob,Miranda,Jack,Mordin,Grunt,Kasumi,Legion,Samara,Tali,"
+      "Thane,Zaeed,Morinth,Crew,Shepard,Traversals\r\n"
+      "disloyal,loyal,disloyal,dead,disloyal,loyal,,dead,,dead,,,,"
+      "True,True,4\r\n")
+
+  def test_names(self):
+    output = io.StringIO(newline="")
+    write_csv(self.outcomes.items(), output, delimiter=";", per_ally=False)
+    self.assertEqual(output.getvalue(),
+      "Spared;Loyal;Dead;Crew;Shepard;Traversals\r\n"
+      "Garrus, Grunt, Jacob, Miranda, and Mordin;Grunt and Jacob;"
+      "Jack, Legion, and Tali;True;True;4\r\n")
+
+
+class WriteSqliteTest(unittest.TestCase):
+  def setUp(self) -> None:
+    directory = tempfile.TemporaryDirectory()
+    self.addCleanup(directory.cleanup)
+    self.path = os.path.join(directory.name, "outcomes.db")
+
+  def query(self, sql: str) -> list[tuple]:
+    connection = sqlite3.connect(self.path)
+    try:
+      return connection.execute(sql).fetchall()
+    finally:
+      connection.close()
+
+  def test_outcomes(self):
+    write_sqlite(OUTCOMES.items(), self.path)
+    self.assertEqual(self.query("SELECT COUNT(*) FROM outcomes"), [(3,)])
+    self.assertEqual(
+      self.query("SELECT SUM(traversals) FROM outcomes WHERE Zaeed"), [(8,)])
+    self.assertEqual(self.query(
+      "SELECT spared, loyalty FROM outcomes WHERE NOT crew"),
+      [((Ally.Garrus | Ally.Tali | Ally.Zaeed).value, Ally.Garrus.value)])
+    self.assertEqual(
+      self.query("SELECT COUNT(*) FROM outcomes WHERE Mordin"), [(0,)])
+    self.assertEqual(self.query(
+      "SELECT name FROM sqlite_master WHERE name = 'witnesses'"), [])
+
+  def test_replace(self):
+    write_sqlite(OUTCOMES.items(), self.path)
+    write_sqlite([(_ZAEED_DIES, OUTCOMES[_ZAEED_DIES])], self.path)
+    self.assertEqual(self.query("SELECT outcome FROM outcomes"),
+                     [(_ZAEED_DIES,)])
+
+  def test_witnesses(self):
+    spared = REQUIRED & ~Ally.Jack | Ally.Grunt
+    outcome = encode_outcome(spared.value, Ally.Grunt.value, True)
+    write_sqlite([(outcome, (4, _PARTIAL_ROSTER))], self.path, witnesses=True)
+    self.assertEqual(
+      self.query("SELECT COUNT(*) FROM outcomes WHERE Mordin"), [(1,)])
+    [(traversal, description)] = self.query(
+      "SELECT traversal, description FROM witnesses")
+    self.assertEqual(int(traversal), _PARTIAL_ROSTER)
+    self.assertEqual(description, describe_traversal(_PARTIAL_ROSTER))
+
+
+if __name__ == "__main__":
+  unittest.main()
\ No newline at end of file